@@ -0,0 +1,331 @@
+const data = JSON.parse(document.getElementById("chartr-data").textContent);
+
+const viewport = document.getElementById("viewport");
+const chart = viewport.querySelector("svg");
+const details = document.getElementById("details");
+const detailsBody = document.getElementById("details-body");
+
+const baseWidth = parseFloat(chart.getAttribute("width"));
+const baseHeight = parseFloat(chart.getAttribute("height"));
+chart.setAttribute("viewBox", `0 0 ${baseWidth} ${baseHeight}`);
+
+const eventsById = new Map(data.events.map((e) => [e.id, e]));
+const elementsById = new Map(
+    Array.from(chart.querySelectorAll("[data-event-id]"),
+               (el) => [parseInt(el.dataset.eventId), el]));
+const groupsByActor = new Map(
+    Array.from(chart.querySelectorAll("g.actor"), (g) => [g.dataset.actor, g]));
+
+function formatMicros(us) {
+    let abs = Math.abs(us);
+    if (abs < 1000) {
+        return `${us}µs`
+    } else if (abs < 1000000) {
+        return `${+(us / 1000).toFixed(3)}ms`
+    } else if (abs < 60000000) {
+        return `${+(us / 1000000).toFixed(3)}s`
+    } else if (abs < 3600000000) {
+        return `${+(us / 60000000).toFixed(3)}min`
+    } else {
+        return `${+(us / 3600000000).toFixed(3)}h`
+    }
+}
+
+function formatTime(us) {
+    if (data.epochMs === null) {
+        return formatMicros(us)
+    }
+
+    // Shift by the display offset so the UTC fields read as local time
+    let time = new Date(data.epochMs + us / 1000 + data.epochOffsetMs);
+    return time.toISOString().replace("T", " ").substring(0, 23)
+}
+
+/* Zooming and panning */
+
+let zoom = 1;
+
+function setZoom(level, anchorX = viewport.clientWidth / 2, anchorY = viewport.clientHeight / 2) {
+    level = Math.min(Math.max(level, 0.05), 50);
+
+    // Keep the point under the anchor in place
+    let x = (viewport.scrollLeft + anchorX) / zoom;
+    let y = (viewport.scrollTop + anchorY) / zoom;
+
+    zoom = level;
+    chart.setAttribute("width", baseWidth * zoom);
+    chart.setAttribute("height", baseHeight * zoom);
+    viewport.scrollLeft = x * zoom - anchorX;
+    viewport.scrollTop = y * zoom - anchorY;
+    document.getElementById("zoom-level").textContent = `${Math.round(zoom * 100)}%`;
+}
+
+document.getElementById("zoom-in").addEventListener("click", () => setZoom(zoom * 1.5));
+document.getElementById("zoom-out").addEventListener("click", () => setZoom(zoom / 1.5));
+document.getElementById("zoom-reset").addEventListener("click", () => {
+    setZoom(Math.min(1, viewport.clientWidth / baseWidth), 0, 0);
+});
+
+viewport.addEventListener("wheel", (e) => {
+    if (!e.ctrlKey) {
+        return;
+    }
+    e.preventDefault();
+    let bounds = viewport.getBoundingClientRect();
+    setZoom(zoom * Math.exp(-e.deltaY / 300), e.clientX - bounds.left, e.clientY - bounds.top);
+}, { passive: false });
+
+let pan = null;
+viewport.addEventListener("mousedown", (e) => {
+    if (e.button !== 0) {
+        return;
+    }
+    pan = { x: e.clientX, y: e.clientY, left: viewport.scrollLeft, top: viewport.scrollTop, moved: false };
+});
+document.addEventListener("mousemove", (e) => {
+    if (pan === null) {
+        return;
+    }
+    let dx = e.clientX - pan.x;
+    let dy = e.clientY - pan.y;
+    if (Math.abs(dx) + Math.abs(dy) > 3) {
+        pan.moved = true;
+        viewport.classList.add("panning");
+    }
+    viewport.scrollLeft = pan.left - dx;
+    viewport.scrollTop = pan.top - dy;
+});
+document.addEventListener("mouseup", () => {
+    viewport.classList.remove("panning");
+    // Let the click that ends a drag see it was a drag
+    setTimeout(() => { pan = null; }, 0);
+});
+
+/* Searching and filtering */
+
+const actorSearch = document.getElementById("actor-search");
+const fieldFilter = document.getElementById("field-filter");
+
+function actorMatches(actor) {
+    let query = actorSearch.value.trim().toLowerCase();
+    return actor.toLowerCase().includes(query);
+}
+
+// "key=value" matches events with that field containing the value, "key="
+// events with the field at all, and anything else is looked for in the
+// fields, label and tooltip of the event
+function eventMatches(event) {
+    let query = fieldFilter.value.trim().toLowerCase();
+    if (query === "") {
+        return true;
+    }
+
+    let entries = Object.entries(event.fields).map(([k, v]) => [k.toLowerCase(), v.toLowerCase()]);
+    let split = query.indexOf("=");
+    if (split > 0) {
+        let key = query.substring(0, split).trim();
+        let value = query.substring(split + 1).trim();
+        return entries.some(([k, v]) => k === key && v.includes(value));
+    }
+
+    let text = [event.value, event.tooltip || ""].concat(entries.flat()).join("\n").toLowerCase();
+    return text.includes(query);
+}
+
+function applyFilters() {
+    let shown = 0;
+    for (let event of data.events) {
+        let visible = actorMatches(event.actor) && eventMatches(event);
+        if (visible) {
+            shown += 1;
+        }
+        let element = elementsById.get(event.id);
+        if (element) {
+            element.classList.toggle("dimmed", !visible);
+        }
+    }
+
+    for (let [actor, group] of groupsByActor) {
+        group.classList.toggle("dimmed", !actorMatches(actor));
+    }
+
+    for (let row of document.querySelectorAll("#actors tbody tr")) {
+        row.hidden = !actorMatches(row.dataset.actor);
+    }
+
+    let filtering = actorSearch.value.trim() !== "" || fieldFilter.value.trim() !== "";
+    document.getElementById("match-count").textContent =
+        filtering ? `${shown} of ${data.events.length} events` : "";
+}
+
+actorSearch.addEventListener("input", applyFilters);
+fieldFilter.addEventListener("input", applyFilters);
+
+/* Actor summary table */
+
+// Rows are summarized by chartr, as for `chartr blame`
+const summaries = data.summaries;
+
+let sortKey = "total";
+let sortDescending = true;
+
+function renderTable() {
+    summaries.sort((a, b) => {
+        let order = a[sortKey] < b[sortKey] ? -1 : a[sortKey] > b[sortKey] ? 1 : 0;
+        return sortDescending ? -order : order;
+    });
+
+    let body = document.querySelector("#actors tbody");
+    body.replaceChildren(...summaries.map((summary) => {
+        let row = document.createElement("tr");
+        row.dataset.actor = summary.id;
+        for (let [value, number] of [
+            [summary.id, false],
+            [summary.count, true],
+            [formatMicros(summary.total), true],
+            [formatTime(summary.first), true],
+            [formatTime(summary.last), true],
+        ]) {
+            let cell = document.createElement("td");
+            cell.textContent = value;
+            if (number) {
+                cell.className = "number";
+            }
+            row.appendChild(cell);
+        }
+        row.addEventListener("click", () => showActor(summary.id));
+        return row;
+    }));
+
+    for (let header of document.querySelectorAll("#actors th")) {
+        header.classList.toggle("sorted-asc", header.dataset.key === sortKey && !sortDescending);
+        header.classList.toggle("sorted-desc", header.dataset.key === sortKey && sortDescending);
+    }
+    applyFilters();
+}
+
+for (let header of document.querySelectorAll("#actors th")) {
+    header.addEventListener("click", () => {
+        if (sortKey === header.dataset.key) {
+            sortDescending = !sortDescending;
+        } else {
+            sortKey = header.dataset.key;
+            sortDescending = sortKey !== "id";
+        }
+        renderTable();
+    });
+}
+
+/* Highlighting and details */
+
+function scrollIntoView(element) {
+    let bounds = element.getBoundingClientRect();
+    let view = viewport.getBoundingClientRect();
+    viewport.scrollLeft += bounds.left - view.left - 40;
+    viewport.scrollTop += bounds.top - view.top - view.height / 3;
+}
+
+function showActor(actor) {
+    for (let [id, group] of groupsByActor) {
+        group.classList.toggle("highlighted", id === actor);
+    }
+    let group = groupsByActor.get(actor);
+    if (group) {
+        scrollIntoView(group);
+    }
+}
+
+function eventLink(id) {
+    let event = eventsById.get(id);
+    let link = document.createElement("a");
+    link.textContent = event ? `${event.actor}${event.value ? ": " + event.value : ""} (#${id})` : `#${id}`;
+    link.addEventListener("click", () => selectEvent(id, true));
+    return link;
+}
+
+function selectEvent(id, scroll) {
+    let event = eventsById.get(id);
+    if (!event) {
+        return;
+    }
+
+    for (let element of chart.querySelectorAll(".selected")) {
+        element.classList.remove("selected");
+    }
+    let element = elementsById.get(id);
+    if (element) {
+        element.classList.add("selected");
+        if (scroll) {
+            scrollIntoView(element);
+        }
+    }
+
+    let rows = [
+        ["Actor", event.actor],
+        ["Label", event.value],
+        ["Id", `#${id}`],
+    ];
+    if (event.instant) {
+        rows.push(["At", formatTime(event.start)]);
+    } else {
+        rows.push(["Start", formatTime(event.start)]);
+        rows.push(["End", event.end === null ? "still open" : formatTime(event.end)]);
+        if (event.end !== null) {
+            rows.push(["Duration", formatMicros(event.end - event.start)]);
+        }
+    }
+    if (event.tooltip) {
+        rows.push(["Tooltip", event.tooltip]);
+    }
+    for (let [key, value] of Object.entries(event.fields)) {
+        rows.push([key, value]);
+    }
+    if (event.after.length > 0) {
+        rows.push(["After", event.after.map(eventLink)]);
+    }
+    if (event.before.length > 0) {
+        rows.push(["Before", event.before.map(eventLink)]);
+    }
+
+    let list = document.createElement("dl");
+    for (let [name, value] of rows) {
+        if (value === "") {
+            continue;
+        }
+        let term = document.createElement("dt");
+        term.textContent = name;
+        let definition = document.createElement("dd");
+        if (Array.isArray(value)) {
+            value.forEach((link, i) => {
+                if (i > 0) {
+                    definition.append(document.createElement("br"));
+                }
+                definition.append(link);
+            });
+        } else {
+            definition.textContent = value;
+        }
+        list.append(term, definition);
+    }
+    detailsBody.replaceChildren(list);
+    details.hidden = false;
+}
+
+chart.addEventListener("click", (e) => {
+    if (pan !== null && pan.moved) {
+        return;
+    }
+    let element = e.target.closest("[data-event-id]");
+    if (element) {
+        selectEvent(parseInt(element.dataset.eventId), false);
+    }
+});
+
+document.getElementById("details-close").addEventListener("click", () => {
+    details.hidden = true;
+    for (let element of chart.querySelectorAll(".selected")) {
+        element.classList.remove("selected");
+    }
+});
+
+renderTable();
@@ -1,22 +1,38 @@
 function renderMicros(us) {
-    if (us < 1000) {
-        return `${us}us`
-    } else if (us < 1000000) {
+    let abs = Math.abs(us);
+    if (abs < 1000) {
+        return `${us}µs`
+    } else if (abs < 1000000) {
         return `${us / 1000}ms`
-    } else {
+    } else if (abs < 60000000) {
         return `${us / 1000000}s`
+    } else if (abs < 3600000000) {
+        return `${us / 60000000}min`
+    } else {
+        return `${us / 3600000000}h`
+    }
+}
+
+function renderTime(us) {
+    if (__EPOCH_MS__ === null) {
+        return renderMicros(us)
     }
+
+    // Shift by the display offset so the UTC fields read as local time
+    let time = new Date(__EPOCH_MS__ + us / 1000 + __EPOCH_OFFSET_MS__);
+    return time.toISOString().substring(11, 23)
 }
 
 function updateIndicator(x, y) {
     let indicator = document.getElementById("indicator");
     indicator.setAttribute("x", x);
     indicator.setAttribute("y", __HEADING_HEIGHT__);
+    indicator.setAttribute("visibility", "visible");
 
     let text = document.getElementById("indicator-text");
     text.setAttribute("x", x + 10);
     text.setAttribute("y", y - 10);
-    text.innerHTML = renderMicros( (x - __LEFT_OFFSET__) * __US_PER_PIXEL__)
+    text.innerHTML = renderTime( (x - __LEFT_OFFSET__) * __US_PER_PIXEL__)
     lastMousePos = {
         x: x,
         y: y
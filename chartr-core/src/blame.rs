@@ -0,0 +1,294 @@
+use serde::Serialize;
+use std::io::Write;
+
+use crate::error::Result;
+use crate::event::{ActorId, Epoch, Event, EventKind, EventStore};
+use crate::units;
+
+/// How much time an actor takes up, in the spirit of `systemd-analyze blame`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Blame {
+    pub actor: ActorId,
+    /// Microseconds spent in spans, counting overlapping spans once
+    pub total_us: i64,
+    pub first_start_us: i64,
+    pub last_end_us: i64,
+    pub spans: usize,
+    /// Whether any span is still open, and so counted up to the end of
+    /// the chart
+    pub open: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortBy {
+    /// Longest total first
+    Total,
+    /// Earliest first
+    FirstStart,
+    /// Latest first
+    LastEnd,
+}
+
+/// The latest time in the chart, which open spans are drawn up to
+pub(crate) fn chart_end(events: &EventStore) -> Option<i64> {
+    events
+        .all_events()
+        .filter_map(Event::end_time)
+        .chain(events.all_events().map(Event::start_time))
+        .max()
+}
+
+/// Merge overlapping `(start, end)` ranges and sum what they cover
+fn merged_length(mut ranges: Vec<(i64, i64)>) -> i64 {
+    ranges.sort();
+
+    let mut total = 0;
+    let mut current: Option<(i64, i64)> = None;
+    for (start, end) in ranges {
+        match current {
+            Some((from, to)) if start <= to => current = Some((from, to.max(end))),
+            _ => {
+                total += current.map_or(0, |(from, to)| to - from);
+                current = Some((start, end));
+            }
+        }
+    }
+    total + current.map_or(0, |(from, to)| to - from)
+}
+
+/// The time `actor` spends in spans, counting overlapping spans once.
+/// Open spans are counted up to `end`.
+pub(crate) fn busy_time(events: &EventStore, actor: &ActorId, end: i64) -> i64 {
+    merged_length(
+        events
+            .events_for(actor)
+            .into_iter()
+            .flatten()
+            .filter_map(|event| match event.kind {
+                EventKind::Span(start, _) => Some((start, event.end_time().unwrap_or(end))),
+                EventKind::Instant(_) => None,
+            })
+            .collect(),
+    )
+}
+
+/// Summarize every actor with events, sorted by `sort`. Ties are broken by
+/// actor id so the order is stable.
+pub fn blame(events: &EventStore, sort: SortBy) -> Vec<Blame> {
+    let Some(end) = chart_end(events) else {
+        return vec![];
+    };
+
+    let mut blames = events
+        .actors()
+        .filter_map(|actor| {
+            let actor_events = events.events_for(&actor).ok()?.collect::<Vec<_>>();
+            let first_start_us = actor_events.iter().map(|e| e.start_time()).min()?;
+            let last_end_us = actor_events
+                .iter()
+                .map(|e| e.end_time().unwrap_or(end))
+                .max()?;
+            let spans = actor_events
+                .iter()
+                .filter(|e| matches!(e.kind, EventKind::Span(..)))
+                .count();
+            let open = actor_events
+                .iter()
+                .any(|e| matches!(e.kind, EventKind::Span(_, None)));
+
+            Some(Blame {
+                total_us: busy_time(events, &actor, end),
+                actor,
+                first_start_us,
+                last_end_us,
+                spans,
+                open,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    blames.sort_by(|a, b| {
+        match sort {
+            SortBy::Total => b.total_us.cmp(&a.total_us),
+            SortBy::FirstStart => a.first_start_us.cmp(&b.first_start_us),
+            SortBy::LastEnd => b.last_end_us.cmp(&a.last_end_us),
+        }
+        .then_with(|| a.actor.cmp(&b.actor))
+    });
+    blames
+}
+
+/// Write one line per actor with the value it is sorted by, then its name.
+/// Times are shown on the wall clock when the chart has an `epoch`, and
+/// from the first event otherwise.
+pub fn write_plain(
+    blames: &[Blame],
+    sort: SortBy,
+    epoch: Option<&Epoch>,
+    mut writer: impl Write,
+) -> Result<()> {
+    let first = blames.iter().map(|blame| blame.first_start_us).min();
+    let time = |us| match epoch {
+        Some(epoch) => units::format_wall_clock(epoch.start(), us, 1000),
+        None => format!("+{}", units::format_micros(us - first.unwrap_or(us))),
+    };
+    let values = blames
+        .iter()
+        .map(|blame| {
+            let value = match sort {
+                SortBy::Total => units::format_micros(blame.total_us),
+                SortBy::FirstStart => time(blame.first_start_us),
+                SortBy::LastEnd => time(blame.last_end_us),
+            };
+            (value, blame)
+        })
+        .collect::<Vec<_>>();
+
+    let width = values
+        .iter()
+        .map(|(value, _)| value.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (value, blame) in values {
+        let open = if blame.open { " (still open)" } else { "" };
+        writeln!(writer, "{value:>width$} {}{open}", blame.actor)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_json(blames: &[Blame], mut writer: impl Write) -> Result<()> {
+    serde_json::to_writer_pretty(&mut writer, blames)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_csv(blames: &[Blame], writer: impl Write) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for blame in blames {
+        writer.serialize(blame).map_err(std::io::Error::from)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Actor;
+
+    #[test]
+    fn test_blame() {
+        let mut context = EventStore::default();
+        let build = context.register_actor(Actor::new("build")).unwrap();
+        let deploy = context.register_actor(Actor::new("deploy")).unwrap();
+        context.register_actor(Actor::new("idle")).unwrap();
+        for kind in [
+            EventKind::Span(100, Some(400)),
+            EventKind::Span(300, Some(400)),
+            EventKind::Span(900, Some(100)),
+            EventKind::Instant(1200),
+        ] {
+            context.add_event(&build, Event::new(kind)).unwrap();
+        }
+        context
+            .add_event(&deploy, Event::new(EventKind::Span(0, None)))
+            .unwrap();
+
+        crate::render::RendererBuilder::default()
+            .build()
+            .render("/tmp/blame.svg", &context)
+            .unwrap();
+        let (_, events) = crate::load("/tmp/blame.svg").unwrap();
+
+        // Overlapping spans are counted once, open ones run to the end, and
+        // actors without events are left out
+        let blames = blame(&events, SortBy::Total);
+        let summary = blames
+            .iter()
+            .map(|b| (b.actor.as_str(), b.total_us, b.spans, b.open))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [("deploy", 1200, 1, true), ("build", 700, 3, false)]);
+        assert_eq!((blames[1].first_start_us, blames[1].last_end_us), (100, 1200));
+
+        let order = |sort| {
+            blame(&events, sort)
+                .into_iter()
+                .map(|b| b.actor)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(order(SortBy::FirstStart), ["deploy", "build"]);
+        assert_eq!(order(SortBy::LastEnd), ["build", "deploy"]);
+
+        let mut plain = vec![];
+        write_plain(&blames, SortBy::Total, None, &mut plain).unwrap();
+        assert_eq!(
+            String::from_utf8(plain).unwrap(),
+            "1.2ms deploy (still open)\n700µs build\n"
+        );
+
+        let mut json = vec![];
+        write_json(&blames, &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json[1]["actor"], "build");
+        assert_eq!(json[1]["total_us"], 700);
+
+        let mut csv = vec![];
+        write_csv(&blames, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            [
+                "actor,total_us,first_start_us,last_end_us,spans,open",
+                "deploy,1200,0,1200,1,true",
+                "build,700,100,1200,3,false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blame_open_spans() {
+        let mut context = EventStore::default();
+        let worker = context.register_actor(Actor::new("worker")).unwrap();
+        let late = context.register_actor(Actor::new("late")).unwrap();
+        for kind in [
+            EventKind::Span(0, Some(300)),
+            EventKind::Span(200, None),
+            EventKind::Span(100, None),
+        ] {
+            context.add_event(&worker, Event::new(kind)).unwrap();
+        }
+        context
+            .add_event(&late, Event::new(EventKind::Span(1000, None)))
+            .unwrap();
+
+        // Open spans run to the latest time in the chart, which an open
+        // span only reaches by starting there, and they overlap like any
+        // other span
+        let blames = blame(&context, SortBy::Total);
+        let summary = blames
+            .iter()
+            .map(|b| (b.actor.as_str(), b.total_us, b.last_end_us, b.open))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [("worker", 1000, 1000, true), ("late", 0, 1000, true)]);
+
+        // Plain output counts from the first event without an epoch, and
+        // uses the wall clock with one
+        let mut plain = vec![];
+        write_plain(&blames, SortBy::FirstStart, None, &mut plain).unwrap();
+        assert_eq!(
+            String::from_utf8(plain).unwrap(),
+            "+0µs worker (still open)\n+1ms late (still open)\n"
+        );
+
+        let epoch = Epoch::new(time::macros::datetime!(2024-01-02 03:04:05 UTC));
+        let mut plain = vec![];
+        write_plain(&blames, SortBy::LastEnd, Some(&epoch), &mut plain).unwrap();
+        assert!(String::from_utf8(plain)
+            .unwrap()
+            .starts_with("03:04:05.001 worker"));
+
+        assert!(blame(&EventStore::default(), SortBy::Total).is_empty());
+    }
+}
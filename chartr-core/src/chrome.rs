@@ -0,0 +1,511 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use crate::error::{Error, Result};
+use crate::event::{EventKind, EventStore};
+use crate::import::Record;
+
+/// All actors are exported as threads of this one process
+const EXPORT_PID: u64 = 1;
+
+/// A single entry of the Chrome Trace Event Format. Only the parts chartr
+/// can show are kept.
+#[derive(Deserialize)]
+struct TraceEvent {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    cat: Option<String>,
+    ph: String,
+    #[serde(default)]
+    ts: f64,
+    #[serde(default)]
+    dur: Option<f64>,
+    // Some producers use strings for these
+    #[serde(default)]
+    pid: Value,
+    #[serde(default)]
+    tid: Value,
+    #[serde(default)]
+    args: BTreeMap<String, Value>,
+}
+
+/// Traces are either a bare array of events or an object holding them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Trace {
+    Array(Vec<TraceEvent>),
+    Object {
+        #[serde(rename = "traceEvents")]
+        trace_events: Vec<TraceEvent>,
+    },
+}
+
+/// Render an arg value without the quotes json would put on strings
+fn arg_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Fields are copied onto the svg elements, so keep them valid attribute
+/// names under the `data-` prefix
+fn field_name(key: &str) -> String {
+    let key = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("data-{key}")
+}
+
+struct Slice {
+    name: String,
+    cat: Option<String>,
+    args: BTreeMap<String, Value>,
+}
+
+impl Slice {
+    fn record(self, actor: &str, start: i64, duration: Option<u64>, instant: bool) -> Record {
+        let mut fields = self
+            .args
+            .iter()
+            .map(|(key, value)| (field_name(key), arg_string(value)))
+            .collect::<BTreeMap<_, _>>();
+        if let Some(cat) = &self.cat {
+            fields.insert("data-category".into(), cat.clone());
+        }
+
+        let tooltip = std::iter::once(self.name.clone())
+            .chain(
+                self.args
+                    .iter()
+                    .map(|(key, value)| format!("{key}: {}", arg_string(value))),
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Record::Event {
+            actor: actor.to_owned(),
+            start,
+            duration,
+            endless: !instant && duration.is_none(),
+            fields,
+            color: None,
+            tooltip: Some(tooltip),
+            value: self.name,
+            key: None,
+        }
+    }
+}
+
+/// Whether `start`, the beginning of some input, looks like a Chrome
+/// trace: a json array, or an object that starts with the events
+pub fn is_trace(start: &[u8]) -> bool {
+    match start.trim_ascii_start().split_first() {
+        Some((b'[', _)) => true,
+        Some((b'{', rest)) => rest.trim_ascii_start().starts_with(b"\"traceEvents\""),
+        _ => false,
+    }
+}
+
+/// Read a Chrome Trace Event Format document (as written by Chrome,
+/// Perfetto, cargo or clang's `-ftime-trace`) into records. Each thread
+/// becomes an actor, complete and begin/end events become spans and
+/// instant events become instants. Times are shifted so the trace starts
+/// at zero.
+pub fn read_trace(reader: impl Read) -> Result<Vec<Record>> {
+    let trace = serde_json::from_reader(reader).map_err(|err| {
+        if err.is_io() {
+            Error::Io(err.into())
+        } else {
+            Error::Import(format!("Invalid trace: {err}"))
+        }
+    })?;
+    let events = match trace {
+        Trace::Array(events) => events,
+        Trace::Object { trace_events } => trace_events,
+    };
+
+    // Metadata events name the processes and threads
+    let mut process_names = BTreeMap::new();
+    let mut thread_names = BTreeMap::new();
+    for event in events.iter().filter(|e| e.ph == "M") {
+        let Some(name) = event.args.get("name").map(arg_string) else {
+            continue;
+        };
+        match event.name.as_str() {
+            "process_name" => {
+                process_names.insert(arg_string(&event.pid), name);
+            }
+            "thread_name" => {
+                thread_names.insert((arg_string(&event.pid), arg_string(&event.tid)), name);
+            }
+            _ => (),
+        }
+    }
+
+    let origin = events
+        .iter()
+        .filter(|e| e.ph != "M")
+        .map(|e| e.ts)
+        .fold(f64::INFINITY, f64::min);
+    let time = |ts: f64| (ts - origin).round() as i64;
+
+    let mut actors: BTreeMap<(String, String), String> = BTreeMap::new();
+    let mut records = vec![];
+    let mut actor_for = |records: &mut Vec<Record>, event: &TraceEvent| -> String {
+        let key = (arg_string(&event.pid), arg_string(&event.tid));
+        actors
+            .entry(key.clone())
+            .or_insert_with(|| {
+                let id = format!("{}:{}", key.0, key.1);
+                let identity = match (thread_names.get(&key), process_names.get(&key.0)) {
+                    (Some(name), _) | (None, Some(name)) => format!("{name} [{id}]"),
+                    (None, None) => id,
+                };
+                records.push(Record::Actor {
+                    identity: identity.clone(),
+                    tooltip: None,
+                });
+                identity
+            })
+            .clone()
+    };
+
+    // Open begin events per actor, matched to ends in stack order
+    let mut open: BTreeMap<String, Vec<(i64, Slice)>> = BTreeMap::new();
+
+    for event in events {
+        let slice = |event: TraceEvent| Slice {
+            name: event.name,
+            cat: event.cat,
+            args: event.args,
+        };
+
+        match event.ph.as_str() {
+            "X" => {
+                let actor = actor_for(&mut records, &event);
+                let start = time(event.ts);
+                let duration = event.dur.map(|dur| dur.round().max(0.0) as u64);
+                records.push(slice(event).record(&actor, start, duration, false));
+            }
+            "B" => {
+                let actor = actor_for(&mut records, &event);
+                let start = time(event.ts);
+                open.entry(actor).or_default().push((start, slice(event)));
+            }
+            "E" => {
+                let actor = actor_for(&mut records, &event);
+                let end = time(event.ts);
+                if let Some((start, mut begin)) = open.get_mut(&actor).and_then(Vec::pop) {
+                    // End events may carry more args
+                    begin.args.extend(event.args);
+                    let duration = (end - start).max(0) as u64;
+                    records.push(begin.record(&actor, start, Some(duration), false));
+                }
+            }
+            "i" | "I" => {
+                let actor = actor_for(&mut records, &event);
+                let start = time(event.ts);
+                records.push(slice(event).record(&actor, start, None, true));
+            }
+            _ => (),
+        }
+    }
+
+    // Anything never ended is still running at the end of the trace
+    for (actor, slices) in open {
+        for (start, begin) in slices {
+            records.push(begin.record(&actor, start, None, false));
+        }
+    }
+
+    Ok(records)
+}
+
+#[derive(Serialize)]
+struct ExportEvent {
+    name: String,
+    ph: &'static str,
+    ts: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<u64>,
+    pid: u64,
+    tid: u64,
+    /// The scope of instant events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    args: BTreeMap<String, Value>,
+}
+
+/// Write an `EventStore` as a Chrome Trace Event Format document, for
+/// viewing in Perfetto or chrome://tracing. Each actor is a thread, spans
+/// are complete events (or unended begin events if open) and instants are
+/// thread scoped instant events.
+pub fn write_trace(events: &EventStore, writer: impl Write) -> Result<()> {
+    let mut trace = vec![];
+
+    for (index, actor) in events.actors().enumerate() {
+        let tid = index as u64 + 1;
+        trace.push(ExportEvent {
+            name: "thread_name".into(),
+            ph: "M",
+            ts: 0,
+            dur: None,
+            pid: EXPORT_PID,
+            tid,
+            s: None,
+            args: BTreeMap::from([("name".into(), json!(events.get_actor(&actor)?.identity))]),
+        });
+
+        for event in events.events_for(&actor)? {
+            // Undo the `data-` prefix added on import
+            let mut args = event
+                .fields
+                .iter()
+                .map(|(key, value)| {
+                    let key = key.strip_prefix("data-").unwrap_or(key);
+                    (key.to_owned(), json!(value))
+                })
+                .collect::<BTreeMap<_, _>>();
+            if let Some(tooltip) = &event.tooltip {
+                args.insert("tooltip".into(), json!(tooltip));
+            }
+
+            let name = if event.value.is_empty() {
+                events.get_actor(&actor)?.identity.clone()
+            } else {
+                event.value.clone()
+            };
+
+            let (ph, dur, s) = match event.kind {
+                EventKind::Span(_, Some(duration)) => ("X", Some(duration), None),
+                EventKind::Span(_, None) => ("B", None, None),
+                EventKind::Instant(_) => ("i", None, Some("t")),
+            };
+
+            trace.push(ExportEvent {
+                name,
+                ph,
+                ts: event.start_time(),
+                dur,
+                pid: EXPORT_PID,
+                tid,
+                s,
+                args,
+            });
+        }
+    }
+
+    serde_json::to_writer(
+        writer,
+        &json!({
+            "traceEvents": trace,
+            "displayTimeUnit": "ms",
+        }),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Actor, Event};
+    use crate::import;
+
+    #[test]
+    fn test_is_trace() {
+        assert!(is_trace(b"[{\"name\": \"a\", \"ph\": \"X\"}]"));
+        assert!(is_trace(b"\n  {\n  \"traceEvents\": []}"));
+        assert!(!is_trace(b"{\"type\": \"actor\", \"identity\": \"a\"}"));
+        assert!(!is_trace(b"type,actor,start\n"));
+        assert!(!is_trace(b""));
+    }
+
+    #[test]
+    fn test_import_chrome_trace() {
+        let trace = r#"{"traceEvents": [
+            {"name": "process_name", "ph": "M", "pid": 1, "args": {"name": "cargo"}},
+            {"name": "thread_name", "ph": "M", "pid": 1, "tid": 2, "args": {"name": "worker"}},
+            {"name": "compile", "cat": "build", "ph": "X", "ts": 1000.0, "dur": 500.4,
+             "pid": 1, "tid": 2, "args": {"crate": "serde", "opt level": 3}},
+            {"name": "link", "ph": "B", "ts": 1600, "pid": 1, "tid": 3},
+            {"name": "link", "ph": "E", "ts": 1900, "pid": 1, "tid": 3},
+            {"name": "done", "ph": "i", "ts": 2000, "pid": 1, "tid": 2, "s": "t"},
+            {"name": "hang", "ph": "B", "ts": 2100, "pid": "p", "tid": "t"}
+        ]}"#;
+
+        let records = read_trace(trace.as_bytes()).unwrap();
+        let mut context = EventStore::default();
+        import::apply(&mut context, records).unwrap();
+
+        assert_eq!(
+            context.actors().collect::<Vec<_>>(),
+            vec!["cargo [1:3]", "p:t", "worker [1:2]"]
+        );
+
+        let worker = context
+            .events_for(&"worker [1:2]".to_owned())
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(worker[0].kind, EventKind::Span(0, Some(500)));
+        assert_eq!(worker[0].value, "compile");
+        assert_eq!(worker[0].fields["data-crate"], "serde");
+        assert_eq!(worker[0].fields["data-opt_level"], "3");
+        assert_eq!(worker[0].fields["data-category"], "build");
+        assert_eq!(
+            worker[0].tooltip.as_deref(),
+            Some("compile\ncrate: serde\nopt level: 3")
+        );
+        assert_eq!(worker[1].kind, EventKind::Instant(1000));
+
+        let linker = context.events_for(&"cargo [1:3]".to_owned()).unwrap();
+        assert_eq!(
+            linker.map(|e| &e.kind).collect::<Vec<_>>(),
+            vec![&EventKind::Span(600, Some(300))]
+        );
+
+        let hang = context.events_for(&"p:t".to_owned()).unwrap();
+        assert_eq!(
+            hang.map(|e| &e.kind).collect::<Vec<_>>(),
+            vec![&EventKind::Span(1100, None)]
+        );
+
+        // Bare arrays are accepted too
+        assert!(read_trace("[]".as_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_import_chrome_trace_unmatched() {
+        let trace = r#"[
+            {"name": "stray", "ph": "E", "ts": 0, "pid": 1, "tid": 1},
+            {"name": "outer", "ph": "B", "ts": 100, "pid": 1, "tid": 1},
+            {"name": "inner", "ph": "B", "ts": 200, "pid": 1, "tid": 1},
+            {"name": "elsewhere", "ph": "E", "ts": 250, "pid": 1, "tid": 2},
+            {"name": "inner", "ph": "E", "ts": 300, "pid": 1, "tid": 1, "args": {"ok": true}},
+            {"name": "outer", "ph": "E", "ts": 400, "pid": 1, "tid": 1},
+            {"name": "left open", "ph": "B", "ts": 500, "pid": 1, "tid": 1}
+        ]"#;
+
+        let records = read_trace(trace.as_bytes()).unwrap();
+        let mut context = EventStore::default();
+        import::apply(&mut context, records).unwrap();
+
+        // Ends without a begin on their thread are dropped, and the rest
+        // close the latest begin in stack order
+        let spans = |actor: &str| {
+            context
+                .events_for(&actor.to_owned())
+                .unwrap()
+                .map(|e| (e.value.as_str(), &e.kind))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            spans("1:1"),
+            vec![
+                ("outer", &EventKind::Span(100, Some(300))),
+                ("inner", &EventKind::Span(200, Some(100))),
+                ("left open", &EventKind::Span(500, None)),
+            ]
+        );
+        assert!(spans("1:2").is_empty());
+
+        // The end's args are added to the begin's
+        let inner = context.all_events().find(|e| e.value == "inner").unwrap();
+        assert_eq!(inner.fields["data-ok"], "true");
+
+        assert!(read_trace(r#"{"events": []}"#.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_export_chrome_trace() {
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("worker")).unwrap();
+        let event = |kind, value: &str| Event {
+            fields: BTreeMap::from([("data-crate".into(), "serde".into())]),
+            value: value.into(),
+            tooltip: Some("tip".into()),
+            ..Event::new(kind)
+        };
+        context
+            .add_event(&actor, event(EventKind::Span(0, Some(500)), "compile"))
+            .unwrap();
+        context
+            .add_event(&actor, event(EventKind::Instant(600), "done"))
+            .unwrap();
+        context
+            .add_event(&actor, event(EventKind::Span(700, None), ""))
+            .unwrap();
+
+        let mut trace = vec![];
+        write_trace(&context, &mut trace).unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&trace).unwrap();
+        let events = value["traceEvents"].as_array().unwrap();
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[0]["args"]["name"], "worker");
+        assert_eq!(events[1]["ph"], "X");
+        assert_eq!(events[1]["dur"], 500);
+        assert_eq!(events[1]["args"]["crate"], "serde");
+        assert_eq!(events[1]["args"]["tooltip"], "tip");
+        assert_eq!(events[2]["ph"], "i");
+        assert_eq!(events[3]["ph"], "B");
+        assert_eq!(events[3]["name"], "worker");
+
+        // And it reads back in
+        let records = read_trace(trace.as_slice()).unwrap();
+        let mut reimported = EventStore::default();
+        import::apply(&mut reimported, records).unwrap();
+        let kinds = reimported
+            .all_events()
+            .map(|e| &e.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                &EventKind::Span(0, Some(500)),
+                &EventKind::Instant(600),
+                &EventKind::Span(700, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_chrome_trace_threads() {
+        let mut context = EventStore::default();
+        for name in ["a", "b"] {
+            let actor = context.register_actor(Actor::new(name)).unwrap();
+            context
+                .add_event(&actor, Event::new(EventKind::Instant(0)))
+                .unwrap();
+        }
+
+        let mut trace = vec![];
+        write_trace(&context, &mut trace).unwrap();
+        let value: Value = serde_json::from_slice(&trace).unwrap();
+
+        // Each actor is its own thread, and unlabeled events take its name
+        let threads = value["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["ph"] == "i")
+            .map(|e| (e["name"].as_str().unwrap(), e["tid"].as_u64().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(threads, vec![("a", 1), ("b", 2)]);
+
+        let mut empty = vec![];
+        write_trace(&EventStore::default(), &mut empty).unwrap();
+        let value: Value = serde_json::from_slice(&empty).unwrap();
+        assert_eq!(value["traceEvents"], json!([]));
+    }
+}
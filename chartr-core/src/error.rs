@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use crate::event::{ActorId, EventId};
+
+/// The ways working with a chart can fail that callers may want to tell
+/// apart, rather than just report
+#[derive(Debug)]
+pub enum Error {
+    /// An actor that was never registered
+    UnknownActor(ActorId),
+    /// An actor registered a second time
+    DuplicateActor(ActorId),
+    /// An event id that isn't in the store
+    UnknownEvent(EventId),
+    /// An event that can't be added or changed as asked, e.g. a span that
+    /// would end before it starts
+    InvalidEvent(String),
+    /// The chart has no state, e.g. it was not made by chartr or the
+    /// state was stripped by an optimizer
+    NoEmbeddedState,
+    /// Chart state that can't be read or written, or that was written by a
+    /// newer version of chartr
+    InvalidState(String),
+    /// A project path without a `.json` or `.toml` extension
+    UnknownProjectFormat(PathBuf),
+    /// Input that can't be imported, e.g. a malformed record, trace or
+    /// systemd dump
+    Import(String),
+    /// An error applying an imported record, read from this line of the
+    /// input if it came from one
+    Record(Option<usize>, Box<Error>),
+    /// A chart that can't be drawn in the requested format, e.g. one too
+    /// large to rasterize
+    Render(String),
+    Io(std::io::Error),
+}
+
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownActor(actor) => write!(f, "Unknown actor: {actor}"),
+            Error::DuplicateActor(actor) => write!(f, "Actor already registered: {actor}"),
+            Error::UnknownEvent(id) => write!(f, "Unknown event id: {id}"),
+            Error::InvalidEvent(reason) => write!(f, "{reason}"),
+            Error::NoEmbeddedState => write!(f, "No chartr state found in chart"),
+            Error::InvalidState(reason) => write!(f, "Invalid chart state: {reason}"),
+            Error::UnknownProjectFormat(path) => {
+                write!(f, "Unknown project format: {}", path.display())
+            }
+            Error::Import(reason) => write!(f, "{reason}"),
+            Error::Record(Some(line), err) => {
+                write!(f, "Failed to apply the record on line {line}: {err}")
+            }
+            Error::Record(None, err) => write!(f, "Failed to apply a record: {err}"),
+            Error::Render(reason) => write!(f, "{reason}"),
+            Error::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            Error::Io(err.into())
+        } else {
+            Error::InvalidState(err.to_string())
+        }
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::InvalidState(err.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(err: toml::ser::Error) -> Self {
+        Error::InvalidState(err.to_string())
+    }
+}
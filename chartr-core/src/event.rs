@@ -1,15 +1,28 @@
-use anyhow::{bail, ensure, Result};
+use crate::error::{Error, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use time::{OffsetDateTime, UtcOffset};
 
 #[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
 pub enum EventKind {
-    Span(i64, Option<u32>),
+    /// A start time and an optional duration, both in microseconds. Charts
+    /// saved with 32 bit durations load unchanged.
+    Span(i64, Option<u64>),
     Instant(i64),
 }
 
+pub type EventId = u64;
+
+/// An `Event::fields` key holding a whitespace or comma separated list of
+/// event ids that the event explicitly depends on. The `data-` prefix keeps
+/// it a valid attribute when fields are copied into the rendered svg.
+pub const DEPENDS_ON_FIELD: &str = "data-depends-on";
+
 #[derive(PartialEq, Eq, Debug, Deserialize, Serialize)]
 pub struct Event {
+    /// Assigned by the `EventStore` when the event is added
+    #[serde(default)]
+    pub id: Option<EventId>,
     pub fields: BTreeMap<String, String>,
     pub kind: EventKind,
     pub value: String,
@@ -17,6 +30,17 @@ pub struct Event {
 }
 
 impl Event {
+    /// An event of `kind` without fields, a label or a tooltip
+    pub fn new(kind: EventKind) -> Self {
+        Self {
+            id: None,
+            fields: BTreeMap::new(),
+            kind,
+            value: "".into(),
+            tooltip: None,
+        }
+    }
+
     pub fn start_time(&self) -> i64 {
         match self.kind {
             EventKind::Span(start, _) => start,
@@ -26,16 +50,19 @@ impl Event {
 
     pub fn end_time(&self) -> Option<i64> {
         match self.kind {
-            EventKind::Span(start, Some(duration)) => Some(start + duration as i64),
+            EventKind::Span(start, Some(duration)) => Some(start.saturating_add_unsigned(duration)),
             EventKind::Span(_, None) => None,
             EventKind::Instant(instant) => Some(instant),
         }
     }
 }
 
+/// Events are ordered by time. The id breaks ties, so events at the same
+/// times can share a `BTreeSet`.
 impl Ord for Event {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        (self.start_time(), self.end_time()).cmp(&(other.start_time(), other.end_time()))
+        (self.start_time(), self.end_time(), self.id)
+            .cmp(&(other.start_time(), other.end_time(), other.id))
     }
 }
 
@@ -60,10 +87,61 @@ impl Actor {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// A dependency between two events, drawn from the end of `from` to
+/// the start of `to`
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize, Serialize)]
+pub struct Edge {
+    pub from: EventId,
+    pub to: EventId,
+}
+
+/// Anchors the timeline to a wall-clock time
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Epoch {
+    /// The wall-clock time of time zero
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+
+    /// The timezone times are displayed in
+    pub offset: UtcOffset,
+}
+
+impl Epoch {
+    /// An epoch displayed in the same timezone as `time`
+    pub fn new(time: OffsetDateTime) -> Self {
+        Self {
+            time,
+            offset: time.offset(),
+        }
+    }
+
+    /// Time zero in the display timezone
+    pub fn start(&self) -> OffsetDateTime {
+        self.time.to_offset(self.offset)
+    }
+
+    /// The number of microseconds from time zero to `time`
+    pub fn micros_until(&self, time: OffsetDateTime) -> Result<i64> {
+        let micros = (time - self.time).whole_microseconds();
+        i64::try_from(micros)
+            .map_err(|_| Error::InvalidEvent(format!("{} is too far from the epoch", time)))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct EventStore {
     actors: BTreeMap<ActorId, Actor>,
     events: BTreeMap<ActorId, BTreeSet<Event>>,
+    #[serde(default)]
+    edges: Vec<Edge>,
+    #[serde(default)]
+    next_event_id: EventId,
+    #[serde(default)]
+    epoch: Option<Epoch>,
+    /// The `CLOCK_MONOTONIC` time in microseconds that is time zero for
+    /// events recorded as they happen
+    #[serde(default)]
+    monotonic_origin: Option<i64>,
 }
 
 pub type ActorId = String;
@@ -71,33 +149,216 @@ pub type ActorId = String;
 impl EventStore {
     pub fn register_actor(&mut self, actor: Actor) -> Result<ActorId> {
         let actor_id = actor.identity.clone();
-        ensure!(
-            self.actors.insert(actor_id.clone(), actor).is_none(),
-            "Actor already registered"
-        );
-        ensure!(self
-            .events
-            .insert(actor_id.clone(), BTreeSet::new())
-            .is_none());
+        if self.actors.contains_key(&actor_id) {
+            return Err(Error::DuplicateActor(actor_id));
+        }
+        self.actors.insert(actor_id.clone(), actor);
+        self.events.insert(actor_id.clone(), BTreeSet::new());
         Ok(actor_id)
     }
 
-    pub fn add_event(&mut self, actor: &ActorId, event: Event) -> Result<()> {
+    pub fn add_event(&mut self, actor: &ActorId, mut event: Event) -> Result<EventId> {
         let Some(events) = self.events.get_mut(actor) else {
-            bail!("Unknown actor id: {}", actor);
+            return Err(Error::UnknownActor(actor.clone()));
         };
 
+        if let EventKind::Span(start, Some(duration)) = event.kind {
+            if i64::try_from(duration)
+                .ok()
+                .and_then(|duration| start.checked_add(duration))
+                .is_none()
+            {
+                return Err(Error::InvalidEvent(format!(
+                    "Event duration of {}us starting at {}us overflows the timeline",
+                    duration, start
+                )));
+            }
+        }
+
+        let id = self.next_event_id;
+        event.id = Some(id);
+        if !events.insert(event) {
+            return Err(Error::InvalidEvent(format!("Event id {id} is already in use")));
+        }
+        self.next_event_id += 1;
+        Ok(id)
+    }
+
+    /// Close the latest open span of `actor` whose value is `label`,
+    /// ending it at `end`
+    pub fn close_span(&mut self, actor: &ActorId, label: &str, end: i64) -> Result<()> {
+        let Some(events) = self.events.get_mut(actor) else {
+            return Err(Error::UnknownActor(actor.clone()));
+        };
+
+        // Every stored event has an id, from `add_event` or, for charts made
+        // before events had ids, from migration
+        let Some((start, id)) = events
+            .iter()
+            .rev()
+            .find(|e| matches!(e.kind, EventKind::Span(_, None)) && e.value == label)
+            .and_then(|e| Some((e.start_time(), e.id?)))
+        else {
+            return Err(Error::InvalidEvent(format!(
+                "No open span '{}' for actor {}",
+                label, actor
+            )));
+        };
+        if end < start {
+            return Err(Error::InvalidEvent(format!(
+                "Span '{}' can't end before it started",
+                label
+            )));
+        }
+
+        // Changing the duration changes the span's position in the set, so
+        // it has to be taken out first
+        let Some(mut event) = events.extract_if(.., |e| e.id == Some(id)).next() else {
+            return Err(Error::UnknownEvent(id));
+        };
+        event.kind = EventKind::Span(start, Some((end - start) as u64));
+
         events.insert(event);
         Ok(())
     }
 
+    pub fn epoch(&self) -> Option<&Epoch> {
+        self.epoch.as_ref()
+    }
+
+    pub fn set_epoch(&mut self, epoch: Option<Epoch>) {
+        self.epoch = epoch;
+    }
+
+    /// The chart time of the monotonic clock reading `now`. The first
+    /// reading becomes time zero, unless the chart already has an origin.
+    pub fn monotonic_time(&mut self, now: i64) -> i64 {
+        now - *self.monotonic_origin.get_or_insert(now)
+    }
+
+    pub fn monotonic_origin(&self) -> Option<i64> {
+        self.monotonic_origin
+    }
+
+    pub fn set_monotonic_origin(&mut self, origin: Option<i64>) {
+        self.monotonic_origin = origin;
+    }
+
+    pub fn add_edge(&mut self, from: EventId, to: EventId) -> Result<()> {
+        for id in [from, to] {
+            if self.get_event(id).is_none() {
+                return Err(Error::UnknownEvent(id));
+            }
+        }
+
+        let edge = Edge { from, to };
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+        Ok(())
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.iter()
+    }
+
+    /// Explicit dependencies of `event`, gathered from both the edge list
+    /// (indexed by target in `incoming`) and the `DEPENDS_ON_FIELD` hint
+    fn dependencies_of(event: &Event, incoming: &HashMap<EventId, Vec<EventId>>) -> Vec<EventId> {
+        let mut deps = event
+            .fields
+            .get(DEPENDS_ON_FIELD)
+            .map(|hint| {
+                hint.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter_map(|id| id.parse().ok())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if let Some(from) = event.id.and_then(|id| incoming.get(&id)) {
+            deps.extend(from);
+        }
+        deps
+    }
+
+    /// Compute the chain of spans that ends at the latest span end. Each
+    /// span's predecessor is the explicit dependency that finished last, or
+    /// if there are none, the latest span that ended before it started.
+    /// The path is returned in chronological order.
+    pub fn critical_path(&self) -> Vec<(&ActorId, &Event)> {
+        // Sorted by end so the latest span ending before a time can be
+        // binary searched. The sort is stable, so of spans ending together
+        // the last in store order wins.
+        let mut spans = self
+            .events
+            .iter()
+            .flat_map(|(actor, events)| events.iter().map(move |e| (actor, e)))
+            .filter(|(_, e)| matches!(e.kind, EventKind::Span(_, Some(_))))
+            .collect::<Vec<_>>();
+        spans.sort_by_key(|(_, e)| e.end_time());
+
+        let by_id = spans
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (_, e))| Some((e.id?, index)))
+            .collect::<HashMap<_, _>>();
+        let mut incoming = HashMap::<EventId, Vec<EventId>>::new();
+        for edge in self.edges.iter() {
+            incoming.entry(edge.to).or_default().push(edge.from);
+        }
+
+        let Some(mut current) = spans.len().checked_sub(1) else {
+            return vec![];
+        };
+
+        let mut path = vec![current];
+        let mut visited = HashSet::from([current]);
+        loop {
+            let event = spans[current].1;
+            let deps = Self::dependencies_of(event, &incoming);
+
+            // Explicit dependencies are trusted even if their timing overlaps
+            let next = if deps.is_empty() {
+                let start = Some(event.start_time());
+                let ended = spans.partition_point(|(_, e)| e.end_time() <= start);
+                (0..ended).rev().find(|index| !visited.contains(index))
+            } else {
+                deps.iter()
+                    .filter_map(|id| by_id.get(id).copied())
+                    .filter(|index| !visited.contains(index))
+                    .max()
+            };
+
+            match next {
+                Some(next) => {
+                    visited.insert(next);
+                    path.push(next);
+                    current = next;
+                }
+                None => break,
+            }
+        }
+
+        path.reverse();
+        path.into_iter().map(|index| spans[index]).collect()
+    }
+
+    pub fn get_event(&self, id: EventId) -> Option<(&ActorId, &Event)> {
+        self.events.iter().find_map(|(actor, events)| {
+            events
+                .iter()
+                .find(|e| e.id == Some(id))
+                .map(|event| (actor, event))
+        })
+    }
+
     pub fn all_events(&self) -> impl Iterator<Item = &Event> {
         self.events.values().flatten()
     }
 
     pub fn events_for(&self, actor: &ActorId) -> Result<impl Iterator<Item = &Event>> {
         let Some(events) = self.events.get(actor) else {
-            bail!("Unknown actor id: {}", actor);
+            return Err(Error::UnknownActor(actor.clone()));
         };
 
         Ok(events.iter())
@@ -107,16 +368,54 @@ impl EventStore {
         self.events.keys().cloned()
     }
 
-    pub fn get_actor(&self, id: &ActorId) -> &Actor {
-        self.actors.get(id).expect("Invalid actor id")
+    pub fn contains_actor(&self, id: &ActorId) -> bool {
+        self.actors.contains_key(id)
     }
-}
 
-impl Default for EventStore {
-    fn default() -> Self {
-        Self {
-            actors: BTreeMap::new(),
-            events: BTreeMap::new(),
+    pub fn get_actor(&self, id: &ActorId) -> Result<&Actor> {
+        self.actors
+            .get(id)
+            .ok_or_else(|| Error::UnknownActor(id.clone()))
+    }
+
+    /// Check the invariants `register_actor` and `add_event` keep hold for
+    /// a store that was deserialized, and so may have been edited by hand
+    pub(crate) fn validate(&self) -> Result<()> {
+        let invalid = |message: String| Err(Error::InvalidState(message));
+
+        if let Some(actor) = self.events.keys().find(|id| !self.actors.contains_key(*id)) {
+            return invalid(format!("Events are recorded for unknown actor {}", actor));
+        }
+        if let Some(actor) = self.actors.keys().find(|id| !self.events.contains_key(*id)) {
+            return invalid(format!("Actor {} has no event list", actor));
         }
+
+        let mut ids = BTreeSet::new();
+        for event in self.all_events() {
+            let Some(id) = event.id else {
+                return invalid(format!("Event '{}' has no id", event.value));
+            };
+            if !ids.insert(id) {
+                return invalid(format!("Event id {} is used more than once", id));
+            }
+            if id >= self.next_event_id {
+                return invalid(format!(
+                    "Event id {} is not below the next event id {}",
+                    id, self.next_event_id
+                ));
+            }
+        }
+
+        if let Some(edge) = self
+            .edges
+            .iter()
+            .find(|edge| !ids.contains(&edge.from) || !ids.contains(&edge.to))
+        {
+            return invalid(format!(
+                "Edge from {} to {} names an unknown event",
+                edge.from, edge.to
+            ));
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,418 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read};
+
+use crate::error::{Error, Result};
+use crate::event::{Actor, ActorId, Event, EventId, EventKind, EventStore};
+
+/// An event named by an edge record: either the id of an event already in
+/// the chart, or the `key` of an event added earlier in the same import
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum EventRef {
+    Id(EventId),
+    Key(String),
+}
+
+impl EventRef {
+    /// Csv cells are untyped, so numbers are ids and anything else a key
+    fn parse(cell: String) -> Self {
+        match cell.parse() {
+            Ok(id) => EventRef::Id(id),
+            Err(_) => EventRef::Key(cell),
+        }
+    }
+
+    fn resolve(&self, keys: &BTreeMap<String, EventId>) -> Result<EventId> {
+        match self {
+            EventRef::Id(id) => Ok(*id),
+            EventRef::Key(key) => keys
+                .get(key)
+                .copied()
+                .ok_or_else(|| Error::Import(format!("Unknown event key: {}", key))),
+        }
+    }
+}
+
+/// A single change to apply to an `EventStore`
+#[derive(PartialEq, Eq, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    Actor {
+        identity: String,
+        #[serde(default)]
+        tooltip: Option<String>,
+    },
+    Event {
+        actor: ActorId,
+        start: i64,
+        /// Without a duration the event is an instant, or an open span if
+        /// `endless` is set
+        #[serde(default)]
+        duration: Option<u64>,
+        #[serde(default)]
+        endless: bool,
+        #[serde(default)]
+        fields: BTreeMap<String, String>,
+        /// Shorthand for the `fill` field
+        #[serde(default)]
+        color: Option<String>,
+        #[serde(default)]
+        tooltip: Option<String>,
+        #[serde(default)]
+        value: String,
+        /// Names the event for edge records later in the same import,
+        /// which can't know the id it will get
+        #[serde(default)]
+        key: Option<String>,
+    },
+    Edge {
+        from: EventRef,
+        to: EventRef,
+    },
+}
+
+impl Record {
+    /// Apply the record, remembering the ids of keyed events in `keys` so
+    /// later edge records can refer to them
+    pub fn apply(
+        self,
+        events: &mut EventStore,
+        keys: &mut BTreeMap<String, EventId>,
+    ) -> Result<()> {
+        match self {
+            Record::Actor { identity, tooltip } => {
+                events.register_actor(Actor { identity, tooltip })?;
+            }
+            Record::Event {
+                actor,
+                start,
+                duration,
+                endless,
+                mut fields,
+                color,
+                tooltip,
+                value,
+                key,
+            } => {
+                if let Some(key) = key.as_ref().filter(|key| keys.contains_key(*key)) {
+                    return Err(Error::Import(format!("Event key {} is already in use", key)));
+                }
+
+                let kind = match (duration, endless) {
+                    (Some(duration), _) => EventKind::Span(start, Some(duration)),
+                    (None, true) => EventKind::Span(start, None),
+                    (None, false) => EventKind::Instant(start),
+                };
+
+                if let Some(key) = fields.keys().find(|key| !is_attribute_name(key)) {
+                    return Err(Error::Import(format!(
+                        "Field {:?} is not a valid attribute name",
+                        key
+                    )));
+                }
+
+                if let Some(color) = color {
+                    fields.insert("fill".into(), color);
+                }
+
+                let id = events.add_event(
+                    &actor,
+                    Event {
+                        id: None,
+                        fields,
+                        kind,
+                        value,
+                        tooltip,
+                    },
+                )?;
+                if let Some(key) = key {
+                    keys.insert(key, id);
+                }
+            }
+            Record::Edge { from, to } => {
+                events.add_edge(from.resolve(keys)?, to.resolve(keys)?)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fields are copied verbatim onto the svg elements, so their keys must be
+/// usable as xml attribute names
+fn is_attribute_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// The flat form of a `Record` used for csv, with a header row naming
+/// the columns. The `fields` column holds a json object, and `from` and
+/// `to` are ids if they are numbers and keys otherwise.
+#[derive(Deserialize)]
+struct CsvRecord {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    actor: Option<String>,
+    #[serde(default)]
+    start: Option<i64>,
+    #[serde(default)]
+    duration: Option<u64>,
+    #[serde(default)]
+    endless: Option<bool>,
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    tooltip: Option<String>,
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default)]
+    fields: Option<String>,
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    to: Option<String>,
+}
+
+impl TryFrom<CsvRecord> for Record {
+    type Error = Error;
+
+    fn try_from(row: CsvRecord) -> Result<Self> {
+        let missing =
+            |record: &str, column: &str| Error::Import(format!("{record} record has no {column}"));
+        Ok(match row.kind.as_str() {
+            "actor" => Record::Actor {
+                identity: row.actor.ok_or_else(|| missing("Actor", "actor"))?,
+                tooltip: row.tooltip,
+            },
+            "event" => Record::Event {
+                actor: row.actor.ok_or_else(|| missing("Event", "actor"))?,
+                start: row.start.ok_or_else(|| missing("Event", "start"))?,
+                duration: row.duration,
+                endless: row.endless.unwrap_or_default(),
+                fields: match row.fields {
+                    Some(fields) => serde_json::from_str(&fields).map_err(|err| {
+                        Error::Import(format!(
+                            "Event record fields are not a json object of strings: {err}"
+                        ))
+                    })?,
+                    None => BTreeMap::new(),
+                },
+                color: row.color,
+                tooltip: row.tooltip,
+                value: row.value.unwrap_or_default(),
+                key: row.key,
+            },
+            "edge" => Record::Edge {
+                from: EventRef::parse(row.from.ok_or_else(|| missing("Edge", "from"))?),
+                to: EventRef::parse(row.to.ok_or_else(|| missing("Edge", "to"))?),
+            },
+            other => return Err(Error::Import(format!("Unknown record type: {}", other))),
+        })
+    }
+}
+
+/// A record and the line of the input it was read from, if it came from
+/// one, so errors applying it can point back at the input
+#[derive(PartialEq, Eq, Debug)]
+pub struct SourcedRecord {
+    pub line: Option<usize>,
+    pub record: Record,
+}
+
+impl From<Record> for SourcedRecord {
+    fn from(record: Record) -> Self {
+        SourcedRecord { line: None, record }
+    }
+}
+
+/// Read one json record per line, skipping blank lines
+pub fn read_json_lines(reader: impl BufRead) -> Result<Vec<SourcedRecord>> {
+    let mut records = vec![];
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(SourcedRecord {
+            line: Some(number + 1),
+            record: serde_json::from_str(&line).map_err(|err| {
+                Error::Import(format!("Invalid record on line {}: {}", number + 1, err))
+            })?,
+        });
+    }
+    Ok(records)
+}
+
+pub fn read_csv(reader: impl Read) -> Result<Vec<SourcedRecord>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let headers = reader
+        .headers()
+        .map_err(|err| Error::Import(err.to_string()))?
+        .clone();
+
+    let invalid = |line, err| Error::Import(format!("Invalid record on line {}: {}", line, err));
+    let mut records = vec![];
+    let mut row = csv::StringRecord::new();
+    loop {
+        // Quoted cells can span lines, so ask the reader where rows start
+        let more = reader.read_record(&mut row).map_err(|err| {
+            let line = err.position().map_or(reader.position().line(), |at| at.line()) as usize;
+            invalid(line, err.to_string())
+        })?;
+        if !more {
+            break;
+        }
+        let line = row.position().map_or(0, |at| at.line()) as usize;
+        let record = row
+            .deserialize::<CsvRecord>(Some(&headers))
+            .map_err(|err| Error::Import(err.to_string()))
+            .and_then(Record::try_from)
+            .map_err(|err| invalid(line, err.to_string()))?;
+        records.push(SourcedRecord {
+            line: Some(line),
+            record,
+        });
+    }
+    Ok(records)
+}
+
+/// Apply records in order, stopping at the first one that fails. Event
+/// keys are only known within one call.
+pub fn apply<R: Into<SourcedRecord>>(
+    events: &mut EventStore,
+    records: impl IntoIterator<Item = R>,
+) -> Result<()> {
+    let mut keys = BTreeMap::new();
+    for record in records {
+        let SourcedRecord { line, record } = record.into();
+        record
+            .apply(events, &mut keys)
+            .map_err(|err| Error::Record(line, Box::new(err)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import() {
+        let jsonl = r#"
+            {"type": "actor", "identity": "kernel"}
+            {"type": "actor", "identity": "initrd", "tooltip": "stage: "}
+            {"type": "event", "actor": "kernel", "start": 0, "duration": 1000, "key": "boot"}
+            {"type": "event", "actor": "initrd", "start": 1000, "endless": true, "color": "red"}
+            {"type": "event", "actor": "kernel", "start": 500, "fields": {"data-phase": "early"}}
+            {"type": "edge", "from": "boot", "to": 1}
+        "#;
+        let csv = r#"type,actor,start,duration,endless,color,tooltip,fields,key,from,to
+actor,kernel,,,,,,,,,
+actor,initrd,,,,,stage: ,,,,
+event,kernel,0,1000,,,,,boot,,
+event,initrd,1000,,true,red,,,,,
+event,kernel,500,,,,,"{""data-phase"": ""early""}",,,
+edge,,,,,,,,,boot,1
+"#;
+
+        let from_jsonl = read_json_lines(jsonl.as_bytes()).unwrap();
+        let from_csv = read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(from_jsonl, from_csv);
+
+        let mut context = EventStore::default();
+        apply(&mut context, from_jsonl).unwrap();
+
+        assert_eq!(context.get_event(0).unwrap().1.end_time(), Some(1000));
+        let (_, initrd) = context.get_event(1).unwrap();
+        assert_eq!(initrd.kind, EventKind::Span(1000, None));
+        assert_eq!(initrd.fields["fill"], "red");
+        assert_eq!(context.get_event(2).unwrap().1.fields["data-phase"], "early");
+        assert_eq!(
+            context.edges().collect::<Vec<_>>(),
+            [&crate::event::Edge { from: 0, to: 1 }]
+        );
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let error = |result: Result<Vec<SourcedRecord>>| format!("{:#}", result.unwrap_err());
+
+        // Errors point at the line of the bad record
+        assert!(error(read_json_lines("\n{}\n{".as_bytes()))
+            .starts_with("Invalid record on line 2"));
+        assert!(error(read_csv("type,actor\nactor,a\nevent,a\n".as_bytes()))
+            .contains("line 3: Event record has no start"));
+        assert!(error(read_csv("type\nspan\n".as_bytes())).contains("Unknown record type: span"));
+        assert!(error(read_csv("type,actor,start,fields\nevent,a,0,red\n".as_bytes()))
+            .contains("line 2: Event record fields are not a json object of strings"));
+
+        // Field keys become attribute names in the svg
+        let jsonl = r#"{"type": "event", "actor": "a", "start": 0, "fields": {"bad key": "v"}}"#;
+        let records = read_json_lines(jsonl.as_bytes()).unwrap();
+        let err = apply(&mut EventStore::default(), records).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Failed to apply the record on line 1: \
+             Field \"bad key\" is not a valid attribute name"
+        );
+        let csv = r#"type,actor,start,fields
+event,a,0,"{""x\""y"": ""z""}"
+"#;
+        let records = read_csv(csv.as_bytes()).unwrap();
+        let err = apply(&mut EventStore::default(), records).unwrap_err();
+        assert!(format!("{err:#}").contains(r#"Field "x\"y" is not a valid attribute name"#));
+
+        // Lines are counted in the input, even when a cell spans several
+        let csv = "type,actor,tooltip\nactor,a,\"two\nlines\"\nevent,a\n";
+        assert!(error(read_csv(csv.as_bytes())).contains("line 4: Event record has no start"));
+        let csv = "type,actor,start,tooltip\nactor,a,,\"two\nlines\"\nevent,nobody,0,\n";
+        let records = read_csv(csv.as_bytes()).unwrap();
+        let err = apply(&mut EventStore::default(), records).unwrap_err();
+        assert!(matches!(err, Error::Record(Some(4), _)));
+
+        // Records before a failing one are kept
+        let records = read_json_lines(
+            r#"
+            {"type": "actor", "identity": "a"}
+            {"type": "event", "actor": "nobody", "start": 0}
+            "#
+            .as_bytes(),
+        )
+        .unwrap();
+        let mut context = EventStore::default();
+        let err = apply(&mut context, records).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Failed to apply the record on line 3: Unknown actor: nobody"
+        );
+        assert!(context.contains_actor(&"a".to_owned()));
+
+        // Keys are unique within an import, and forgotten after it
+        let keyed = |key: &str| Record::Event {
+            actor: "a".into(),
+            start: 0,
+            duration: None,
+            endless: false,
+            fields: BTreeMap::new(),
+            color: None,
+            tooltip: None,
+            value: "".into(),
+            key: Some(key.into()),
+        };
+        let err = apply(&mut context, [keyed("x"), keyed("x")]).unwrap_err();
+        assert_eq!(format!("{err:#}"), "Failed to apply a record: Event key x is already in use");
+
+        let edge = Record::Edge {
+            from: EventRef::Key("x".into()),
+            to: EventRef::Id(0),
+        };
+        let err = apply(&mut context, [edge]).unwrap_err();
+        assert_eq!(format!("{err:#}"), "Failed to apply a record: Unknown event key: x");
+    }
+}
@@ -1,25 +1,96 @@
-use anyhow::{bail, Result};
 use std::path::Path;
 
+pub mod blame;
+pub mod chrome;
+pub mod error;
 pub mod event;
+pub mod import;
+pub mod migration;
+pub mod process;
+#[cfg(any(feature = "png", feature = "pdf"))]
+pub mod raster;
 pub mod render;
+mod report;
+pub mod state;
+pub mod systemd;
+pub mod text;
+pub mod units;
+
+pub use error::{Error, Result};
 
 pub fn load(path: impl AsRef<Path>) -> Result<(render::Renderer, event::EventStore)> {
-    let mut buffer = String::new();
-    let parser = svg::open(path, &mut buffer)?;
-
-    for item in parser {
-        match item {
-            svg::parser::Event::Comment(c) => {
-                // The svg crate keeps the added "<!-- " and " -->"
-                // text, so strip it before we deserialize
-                return Ok(serde_json::from_str(&c[5..c.len() - 4])?);
-            }
-            _ => (),
+    load_from_reader(std::fs::File::open(path)?)
+}
+
+/// Load a rendered svg chart, or a png one if the `png` feature is enabled
+pub fn load_from_reader(
+    mut reader: impl std::io::Read,
+) -> Result<(render::Renderer, event::EventStore)> {
+    let mut content = vec![];
+    reader.read_to_end(&mut content)?;
+
+    if content.starts_with(state::PNG_SIGNATURE) {
+        #[cfg(feature = "png")]
+        return state::from_png(&content);
+        #[cfg(not(feature = "png"))]
+        return Err(Error::InvalidState(
+            "loading png charts requires the png feature".into(),
+        ));
+    }
+
+    let content = String::from_utf8(content)
+        .map_err(|_| Error::InvalidState("not an svg or png chart".into()))?;
+    load_from_str(&content)
+}
+
+pub fn load_from_str(svg: &str) -> Result<(render::Renderer, event::EventStore)> {
+    state::from_svg(svg)
+}
+
+/// The formats a chart can be kept in without rendering it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProjectFormat {
+    Json,
+    Toml,
+}
+
+impl ProjectFormat {
+    /// The project format of a path like `boot.chartr.toml`, or `None` if
+    /// it is not a project file (e.g. a rendered svg)
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let name = path.as_ref().file_name()?.to_str()?;
+        if name.ends_with(".chartr.json") {
+            Some(ProjectFormat::Json)
+        } else if name.ends_with(".chartr.toml") {
+            Some(ProjectFormat::Toml)
+        } else {
+            None
         }
     }
+}
 
-    bail!("Failed to find comment to parse")
+pub fn load_project(path: impl AsRef<Path>) -> Result<(render::Renderer, event::EventStore)> {
+    let Some(format) = ProjectFormat::from_path(&path) else {
+        return Err(Error::UnknownProjectFormat(path.as_ref().to_owned()));
+    };
+    let content = std::fs::read_to_string(&path)?;
+    match format {
+        ProjectFormat::Json => state::from_json(&content),
+        ProjectFormat::Toml => state::from_toml(&content),
+    }
+}
+
+pub fn save_project(
+    path: impl AsRef<Path>,
+    renderer: &render::Renderer,
+    events: &event::EventStore,
+) -> Result<()> {
+    let content = match ProjectFormat::from_path(&path) {
+        Some(ProjectFormat::Json) => state::to_json(renderer, events)?,
+        Some(ProjectFormat::Toml) => state::to_toml(renderer, events)?,
+        None => return Err(Error::UnknownProjectFormat(path.as_ref().to_owned())),
+    };
+    Ok(std::fs::write(path, content)?)
 }
 
 #[cfg(test)]
@@ -45,10 +116,11 @@ mod tests {
             .add_event(
                 &actor,
                 Event {
+                    id: None,
                     fields: BTreeMap::from([("fill".into(), "#AB7C94".into())]),
                     kind: EventKind::Span(
                         Duration::from_millis(3500).as_micros() as i64,
-                        Some(Duration::from_millis(750).as_micros() as u32),
+                        Some(Duration::from_millis(750).as_micros() as u64),
                     ),
                     value: "start1".into(),
                     tooltip: None
@@ -60,10 +132,11 @@ mod tests {
             .add_event(
                 &actor,
                 Event {
+                    id: None,
                     fields: BTreeMap::from([("fill".into(), "#AB7C94".into())]),
                     kind: EventKind::Span(
                         Duration::from_millis(1500).as_micros() as i64,
-                        Some(Duration::from_millis(750).as_micros() as u32),
+                        Some(Duration::from_millis(750).as_micros() as u64),
                     ),
                     value: "other1".into(),
                     tooltip: None
@@ -75,10 +148,11 @@ mod tests {
             .add_event(
                 &actor2,
                 Event {
+                    id: None,
                     fields: BTreeMap::from([("fill".into(), "#AB7C94".into())]),
                     kind: EventKind::Span(
                         -(Duration::from_millis(5000).as_micros() as i64),
-                        Some(Duration::from_millis(2000).as_micros() as u32),
+                        Some(Duration::from_millis(2000).as_micros() as u64),
                     ),
                     value: "start2".into(),
                     tooltip: None
@@ -86,9 +160,761 @@ mod tests {
             )
             .unwrap();
 
-        r.render("/tmp/foo.svg", context).unwrap();
+        r.render("/tmp/foo.svg", &context).unwrap();
 
         let (r2, events2) = load("/tmp/foo.svg").unwrap();
-        r2.render("/tmp/foo2.svg", events2).unwrap();
+        r2.render("/tmp/foo2.svg", &events2).unwrap();
+
+        // Rendering in memory produces the same document as rendering to disk
+        let svg = r.render_to_string(&context).unwrap();
+        assert_eq!(svg, std::fs::read_to_string("/tmp/foo.svg").unwrap());
+
+        let mut written = vec![];
+        r.render_to_writer(&mut written, &context).unwrap();
+        assert_eq!(written, svg.as_bytes());
+
+        let (_, events3) = load_from_str(&svg).unwrap();
+        assert_eq!(events3.all_events().count(), 3);
+        let (_, events4) = load_from_reader(svg.as_bytes()).unwrap();
+        assert_eq!(events4.actors().count(), 2);
+    }
+
+    #[test]
+    fn test_render_instant() {
+        let r = RendererBuilder::default().build();
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("myproc")).unwrap();
+
+        context
+            .add_event(
+                &actor,
+                Event {
+                    id: None,
+                    fields: BTreeMap::from([("fill".into(), "#AB7C94".into())]),
+                    kind: EventKind::Instant(Duration::from_millis(500).as_micros() as i64),
+                    value: "milestone".into(),
+                    tooltip: Some("reached".into()),
+                },
+            )
+            .unwrap();
+
+        r.render("/tmp/instant.svg", &context).unwrap();
+
+        let rendered = std::fs::read_to_string("/tmp/instant.svg").unwrap();
+        assert!(rendered.contains("class=\"instant\""));
+        assert!(rendered.contains("<title>reached</title>"));
+
+        let (_, events) = load("/tmp/instant.svg").unwrap();
+        assert_eq!(events.all_events().count(), 1);
+    }
+
+    #[test]
+    fn test_edges() {
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("a")).unwrap();
+        let actor2 = context.register_actor(Actor::new("b")).unwrap();
+
+        let first = context
+            .add_event(
+                &actor,
+                Event {
+                    value: "first".into(),
+                    ..Event::new(EventKind::Span(0, Some(1000)))
+                },
+            )
+            .unwrap();
+
+        let second = context
+            .add_event(
+                &actor2,
+                Event {
+                    value: "second".into(),
+                    ..Event::new(EventKind::Span(2000, Some(1000)))
+                },
+            )
+            .unwrap();
+
+        assert_ne!(first, second);
+        assert!(context.add_edge(first, 42).is_err());
+        context.add_edge(first, second).unwrap();
+
+        RendererBuilder::default()
+            .build()
+            .render("/tmp/edges.svg", &context)
+            .unwrap();
+
+        let rendered = std::fs::read_to_string("/tmp/edges.svg").unwrap();
+        assert!(rendered.contains("class=\"edge\""));
+
+        let (_, events) = load("/tmp/edges.svg").unwrap();
+        assert_eq!(
+            events.edges().collect::<Vec<_>>(),
+            vec![&Edge {
+                from: first,
+                to: second
+            }]
+        );
+        assert_eq!(events.get_event(second).unwrap().1.value, "second");
+    }
+
+    #[test]
+    fn test_identical_events() {
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("worker")).unwrap();
+        let span = |value: &str| Event {
+            value: value.into(),
+            ..Event::new(EventKind::Span(0, Some(1000)))
+        };
+
+        let first = context.add_event(&actor, span("first")).unwrap();
+        let second = context.add_event(&actor, span("second")).unwrap();
+        context.add_edge(first, second).unwrap();
+        assert_eq!(context.events_for(&actor).unwrap().count(), 2);
+
+        let rendered = RendererBuilder::default()
+            .build()
+            .render_to_string(&context)
+            .unwrap();
+        assert!(rendered.contains(&format!("data-event-id=\"{first}\"")));
+        assert!(rendered.contains(&format!("data-event-id=\"{second}\"")));
+
+        let (_, events) = load_from_str(&rendered).unwrap();
+        let values = events.all_events().map(|e| e.value.as_str()).collect::<Vec<_>>();
+        assert_eq!(values, ["first", "second"]);
+    }
+
+    #[test]
+    fn test_critical_path() {
+        let mut context = EventStore::default();
+
+        let add = |context: &mut EventStore, name: &str, start, duration, deps: Option<&str>| {
+            let actor = context.register_actor(Actor::new(name)).unwrap();
+            let mut fields = BTreeMap::new();
+            if let Some(deps) = deps {
+                fields.insert(DEPENDS_ON_FIELD.into(), deps.into());
+            }
+            context
+                .add_event(
+                    &actor,
+                    Event {
+                        fields,
+                        value: name.into(),
+                        ..Event::new(EventKind::Span(start, Some(duration)))
+                    },
+                )
+                .unwrap()
+        };
+
+        let path = |context: &EventStore| {
+            context
+                .critical_path()
+                .into_iter()
+                .map(|(actor, _)| actor.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // Without hints, the latest span ending before each start is used
+        let mut implicit = EventStore::default();
+        add(&mut implicit, "a", 0, 1000, None);
+        add(&mut implicit, "b", 1000, 2000, None);
+        add(&mut implicit, "d", 3000, 1000, None);
+        assert_eq!(path(&implicit), vec!["a", "b", "d"]);
+
+        // An explicit hint overrides the timing based fallback
+        add(&mut context, "a", 0, 1000, None);
+        add(&mut context, "b", 1000, 2000, None);
+        let c = add(&mut context, "c", 500, 1500, None);
+        add(&mut context, "d", 3000, 1000, Some(&c.to_string()));
+        assert_eq!(path(&context), vec!["c", "d"]);
+
+        RendererBuilder::default()
+            .critical_path(true)
+            .build()
+            .render("/tmp/critical.svg", &context)
+            .unwrap();
+
+        let rendered = std::fs::read_to_string("/tmp/critical.svg").unwrap();
+        assert_eq!(rendered.matches("span critical").count(), 2);
+        assert!(rendered.contains("Critical path (3.5ms): c → d"));
+
+        // The total is in the same units as the rest of the chart
+        let mut long = EventStore::default();
+        add(&mut long, "e", 0, 90_000_000, None);
+        let rendered = RendererBuilder::default()
+            .critical_path(true)
+            .build()
+            .render_to_string(&long)
+            .unwrap();
+        assert!(rendered.contains("Critical path (1.5min): e"));
+
+        // Long chains stay fast: every span follows the one before it
+        let mut chain = EventStore::default();
+        let actor = chain.register_actor(Actor::new("chain")).unwrap();
+        for start in 0..20_000 {
+            chain
+                .add_event(&actor, Event::new(EventKind::Span(start, Some(1))))
+                .unwrap();
+        }
+        assert_eq!(chain.critical_path().len(), 20_000);
+    }
+
+    #[test]
+    fn test_long_durations() {
+        // Charts saved with u32 durations still deserialize
+        let legacy: EventStore = serde_json::from_str(
+            r#"{"actors":{"a":{"identity":"a","tooltip":null}},
+                "events":{"a":[{"fields":{},"kind":{"Span":[0,4294967295]},
+                                "value":"","tooltip":null}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            legacy.all_events().next().unwrap().end_time(),
+            Some(u32::MAX as i64)
+        );
+
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("batch")).unwrap();
+
+        let two_hours = Duration::from_secs(2 * 60 * 60).as_micros() as u64;
+
+        context
+            .add_event(&actor, Event::new(EventKind::Span(0, Some(two_hours))))
+            .unwrap();
+        assert_eq!(
+            context.all_events().next().unwrap().end_time(),
+            Some(two_hours as i64)
+        );
+
+        assert!(context
+            .add_event(&actor, Event::new(EventKind::Span(1, Some(i64::MAX as u64))))
+            .is_err());
+        assert!(context
+            .add_event(&actor, Event::new(EventKind::Span(0, Some(u64::MAX))))
+            .is_err());
+    }
+
+    #[test]
+    fn test_pack_lanes() {
+        let mut context = EventStore::default();
+        let pool = context.register_actor(Actor::new("pool")).unwrap();
+        let other = context.register_actor(Actor::new("other")).unwrap();
+
+        let span = |start, duration| Event::new(EventKind::Span(start, Some(duration)));
+
+        // Two overlapping jobs, then one that can reuse the first lane
+        context.add_event(&pool, span(0, 20_000)).unwrap();
+        context.add_event(&pool, span(10_000, 20_000)).unwrap();
+        context.add_event(&pool, span(20_000, 5_000)).unwrap();
+        context.add_event(&other, span(30_000, 5_000)).unwrap();
+
+        RendererBuilder::default()
+            .pack_lanes(true)
+            .build()
+            .render("/tmp/lanes.svg", &context)
+            .unwrap();
+
+        let rendered = std::fs::read_to_string("/tmp/lanes.svg").unwrap();
+        let mut ys = rendered
+            .match_indices(" y=\"")
+            .map(|(i, m)| {
+                let rest = &rendered[i + m.len()..];
+                rest[..rest.find('"').unwrap()].to_owned()
+            })
+            .collect::<Vec<_>>();
+        ys.sort();
+        ys.dedup();
+
+        // Spans sit in the two lanes of "pool" and then in "other" below
+        for y in ["0.5", "20.5", "40.5"] {
+            assert!(ys.contains(&y.to_string()), "missing y={y} in {ys:?}");
+        }
+        assert!(!ys.contains(&"60.5".to_string()));
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        RendererBuilder::default()
+            .heading("configured")
+            .us_per_pixel(std::num::NonZeroU32::new(1000).unwrap())
+            .build()
+            .render("/tmp/configure.svg", &EventStore::default())
+            .unwrap();
+
+        let (r, events) = load("/tmp/configure.svg").unwrap();
+        RendererBuilder::from(r)
+            .side_margin(50.0)
+            .build()
+            .render("/tmp/configure.svg", &events)
+            .unwrap();
+
+        let rendered = std::fs::read_to_string("/tmp/configure.svg").unwrap();
+        assert!(rendered.contains("configured"));
+        assert!(rendered.contains("\"us_per_pixel\":1000"));
+        assert!(rendered.contains("\"side_margin\":50.0"));
+
+        // A zero resolution would divide by zero, so it isn't loaded
+        std::fs::write(
+            "/tmp/configure-zero.svg",
+            rendered.replace("\"us_per_pixel\":1000", "\"us_per_pixel\":0"),
+        )
+        .unwrap();
+        assert!(load("/tmp/configure-zero.svg").is_err());
+    }
+
+    #[test]
+    fn test_fit_width() {
+        let render = |duration: Duration| {
+            let mut context = EventStore::default();
+            let actor = context.register_actor(Actor::new("job")).unwrap();
+            context
+                .add_event(
+                    &actor,
+                    Event::new(EventKind::Span(0, Some(duration.as_micros() as u64))),
+                )
+                .unwrap();
+
+            let fitting = RendererBuilder::default().fit_width(Some(1000.0)).build();
+            fitting.render("/tmp/fit.svg", &context).unwrap();
+
+            // The chart keeps the options it was given, to be fitted
+            // afresh as it grows
+            let (r, _) = load("/tmp/fit.svg").unwrap();
+            assert_eq!(
+                serde_json::to_value(&r).unwrap(),
+                serde_json::to_value(&fitting).unwrap()
+            );
+
+            let rendered = std::fs::read_to_string("/tmp/fit.svg").unwrap();
+            (
+                rendered.matches("<text class=\"label\"").count(),
+                rendered.matches("class=\"subline\"").count(),
+            )
+        };
+
+        // 200ms over 1000px gives 200us/px, so labels every 20ms with three
+        // sublines between them
+        assert_eq!(render(Duration::from_millis(200)), (11, 30));
+
+        // 2h over 1000px gives 7.2s/px, so labels every 20 minutes
+        assert_eq!(render(Duration::from_secs(2 * 60 * 60)), (7, 18));
+    }
+
+    #[test]
+    fn test_time_labels() {
+        let epoch = time::macros::datetime!(2024-01-02 03:04:05 UTC);
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("a")).unwrap();
+        context
+            .add_event(&actor, Event::new(EventKind::Span(0, Some(2_000_000))))
+            .unwrap();
+
+        context.set_epoch(Some(Epoch::new(epoch)));
+
+        RendererBuilder::default()
+            .build()
+            .render("/tmp/labels.svg", &context)
+            .unwrap();
+
+        let rendered = std::fs::read_to_string("/tmp/labels.svg").unwrap();
+        assert!(rendered.contains("\n03:04:06\n"));
+    }
+
+    #[test]
+    fn test_wall_clock_epoch() {
+        let mut context = EventStore::default();
+        let epoch = Epoch {
+            time: time::macros::datetime!(2024-01-02 03:04:05 UTC),
+            offset: time::macros::offset!(+2),
+        };
+        context.set_epoch(Some(epoch));
+
+        let start = epoch
+            .micros_until(time::macros::datetime!(2024-01-02 03:04:06.5 UTC))
+            .unwrap();
+        assert_eq!(start, 1_500_000);
+
+        let actor = context.register_actor(Actor::new("a")).unwrap();
+        context
+            .add_event(
+                &actor,
+                Event {
+                    tooltip: Some("work".into()),
+                    ..Event::new(EventKind::Span(start, Some(500_000)))
+                },
+            )
+            .unwrap();
+
+        RendererBuilder::default()
+            .build()
+            .render("/tmp/epoch.svg", &context)
+            .unwrap();
+
+        let rendered = std::fs::read_to_string("/tmp/epoch.svg").unwrap();
+        assert!(rendered.contains("\n05:04:06\n"));
+        assert!(rendered.contains("work\n05:04:06.500 – 05:04:07.000"));
+
+        let (_, events) = load("/tmp/epoch.svg").unwrap();
+        assert_eq!(events.epoch(), Some(&epoch));
+    }
+
+    #[test]
+    fn test_state_metadata() {
+        let mut context = EventStore::default();
+        let actor = context
+            .register_actor(Actor {
+                identity: "<tricky> & \"quoted\"".into(),
+                tooltip: Some("a -- b --> c".into()),
+            })
+            .unwrap();
+        context
+            .add_event(
+                &actor,
+                Event {
+                    value: "]]> <!-- -->".into(),
+                    tooltip: Some("&amp; --".into()),
+                    ..Event::new(EventKind::Span(0, Some(1000)))
+                },
+            )
+            .unwrap();
+
+        RendererBuilder::default()
+            .build()
+            .render("/tmp/state.svg", &context)
+            .unwrap();
+
+        // Other comments don't get in the way
+        let rendered = std::fs::read_to_string("/tmp/state.svg").unwrap();
+        let rendered = rendered.replacen("<svg", "<!-- unrelated --><svg", 1);
+        std::fs::write("/tmp/state.svg", rendered).unwrap();
+
+        let (_, events) = load("/tmp/state.svg").unwrap();
+        let event = events.all_events().next().unwrap();
+        assert_eq!(event.value, "]]> <!-- -->");
+        assert_eq!(event.tooltip.as_deref(), Some("&amp; --"));
+        assert_eq!(
+            events.get_actor(&actor).unwrap().tooltip.as_deref(),
+            Some("a -- b --> c")
+        );
+
+        // The legacy comment form still loads
+        std::fs::write(
+            "/tmp/legacy.svg",
+            r#"<svg><!-- [{"opts":{"us_per_line":1000000,"sublines":10,"us_per_pixel":10000,
+                "pixels_per_actor":20.0,"actor_margin":0.5,"actor_name_padding":5.0,
+                "top_margin":20.0,"side_margin":20.0,"heading":"old"}},
+                {"actors":{},"events":{}}] --></svg>"#,
+        )
+        .unwrap();
+        load("/tmp/legacy.svg").unwrap();
+
+        std::fs::write("/tmp/stateless.svg", "<svg><rect/></svg>").unwrap();
+        let Err(err) = load("/tmp/stateless.svg") else {
+            panic!("Loaded state from an svg without any");
+        };
+        assert!(matches!(
+            err,
+            Error::NoEmbeddedState
+        ));
+    }
+
+    #[test]
+    fn test_migrations() {
+        use crate::migration::*;
+        use serde_json::json;
+
+        let v0 = json!([{"opts": {}}, {"actors": {}, "events": {}}]);
+        assert_eq!(version_of(&v0).unwrap(), 0);
+
+        let migrated = migrate(v0).unwrap();
+        assert_eq!(version_of(&migrated).unwrap(), CURRENT_VERSION);
+        assert_eq!(migrated["renderer"], json!({"opts": {}}));
+
+        // Events from before ids get them, so their spans can be closed
+        let span = |start, id| json!({"id": id, "fields": {}, "kind": {"Span": [start, null]},
+                                      "value": "build", "tooltip": null});
+        let v0 = json!([
+            serde_json::to_value(Renderer::default()).unwrap(),
+            {"actors": {"a": {"identity": "a", "tooltip": null}},
+             "events": {"a": [span(0, json!(null)), span(10, json!(4)), span(20, json!(null))]}},
+        ]);
+        let (_, mut events) = state::from_json(&v0.to_string()).unwrap();
+        let ids = events.all_events().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids, [Some(5), Some(4), Some(6)]);
+        events.close_span(&"a".to_owned(), "build", 30).unwrap();
+        let actor = events.register_actor(Actor::new("b")).unwrap();
+        assert_eq!(events.add_event(&actor, Event::new(EventKind::Instant(0))).unwrap(), 7);
+
+        assert!(migrate(json!({"version": CURRENT_VERSION + 1})).is_err());
+        assert!(migrate(json!([1, 2, 3])).is_err());
+
+        RendererBuilder::default()
+            .build()
+            .render("/tmp/versioned.svg", &EventStore::default())
+            .unwrap();
+        let rendered = std::fs::read_to_string("/tmp/versioned.svg").unwrap();
+        assert!(rendered.contains(&format!("{{\"version\":{CURRENT_VERSION},")));
+    }
+
+    #[test]
+    fn test_project_files() {
+        let renderer = RendererBuilder::default().heading("project").build();
+
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("a")).unwrap();
+        let span = |kind| Event {
+            fields: BTreeMap::from([("fill".into(), "red".into())]),
+            ..Event::new(kind)
+        };
+        let closed = context
+            .add_event(&actor, span(EventKind::Span(0, Some(1000))))
+            .unwrap();
+        let open = context
+            .add_event(&actor, span(EventKind::Span(500, None)))
+            .unwrap();
+        context.add_edge(closed, open).unwrap();
+
+        // Only event kinds have their open span durations restored
+        let lone = context.register_actor(Actor::new("Span")).unwrap();
+        context
+            .add_event(&lone, span(EventKind::Span(0, Some(100))))
+            .unwrap();
+
+        for path in ["/tmp/project.chartr.toml", "/tmp/project.chartr.json"] {
+            save_project(path, &renderer, &context).unwrap();
+            let (r, events) = load_project(path).unwrap();
+
+            assert_eq!(
+                events.all_events().collect::<Vec<_>>(),
+                context.all_events().collect::<Vec<_>>()
+            );
+            assert_eq!(events.edges().count(), 1);
+
+            r.render("/tmp/project.svg", &events).unwrap();
+            let rendered = std::fs::read_to_string("/tmp/project.svg").unwrap();
+            assert!(rendered.contains("project"));
+        }
+
+        assert!(save_project("/tmp/project.svg", &renderer, &context).is_err());
+        assert_eq!(ProjectFormat::from_path("/tmp/data.json"), None);
+        assert_eq!(
+            ProjectFormat::from_path("/tmp/.123.boot.chartr.toml"),
+            Some(ProjectFormat::Toml)
+        );
+    }
+
+    #[test]
+    fn test_run_process_tree() {
+        let mut context = EventStore::default();
+        let status = process::run(
+            std::process::Command::new("sh").args(["-c", "sleep 0.2; exit 3"]),
+            Duration::from_millis(5),
+            &mut context,
+        )
+        .unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let actors = context.actors().collect::<Vec<_>>();
+        assert!(actors.iter().any(|a| a.starts_with("sh [")), "{actors:?}");
+        assert!(actors.iter().any(|a| a.starts_with("sleep [")), "{actors:?}");
+
+        let sh = actors.iter().find(|a| a.starts_with("sh [")).unwrap();
+        let event = context.events_for(sh).unwrap().next().unwrap();
+        assert!(event.end_time().unwrap() >= 200_000);
+        assert_eq!(
+            event.tooltip.as_deref(),
+            Some("sh -c sleep 0.2; exit 3\nexit status: 3")
+        );
+
+        // Orphans are still followed, and a second command into the same
+        // chart comes after the first
+        let first_end = event.end_time().unwrap();
+        process::run(
+            std::process::Command::new("sh")
+                .args(["-c", "sh -c '(sleep 0.1; exit 4) &'; sleep 0.3"]),
+            Duration::from_millis(5),
+            &mut context,
+        )
+        .unwrap();
+        let tooltips = context
+            .all_events()
+            .filter(|e| e.start_time() >= first_end)
+            .filter_map(|e| e.tooltip.as_deref())
+            .collect::<Vec<_>>();
+        assert!(
+            tooltips.iter().any(|t| t.ends_with("&\nexit status: 4")),
+            "{tooltips:?}"
+        );
+    }
+
+    #[test]
+    fn test_close_span() {
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("script")).unwrap();
+        let open = |start, value: &str| Event {
+            value: value.into(),
+            ..Event::new(EventKind::Span(start, None))
+        };
+
+        let build = context.add_event(&actor, open(100, "build")).unwrap();
+        context.add_event(&actor, open(200, "test")).unwrap();
+
+        assert!(context.close_span(&actor, "deploy", 300).is_err());
+        assert!(context.close_span(&actor, "build", 50).is_err());
+
+        context.close_span(&actor, "build", 400).unwrap();
+        let (_, event) = context.get_event(build).unwrap();
+        assert_eq!(event.kind, EventKind::Span(100, Some(300)));
+
+        // Closed spans can't be closed again
+        assert!(context.close_span(&actor, "build", 500).is_err());
+        assert!(context.close_span(&actor, "test", 500).is_ok());
+
+        // Spans started at the same time are closed by label, not by time
+        let lint = context.add_event(&actor, open(600, "lint")).unwrap();
+        let docs = context.add_event(&actor, open(600, "docs")).unwrap();
+        context.close_span(&actor, "docs", 700).unwrap();
+        assert_eq!(context.get_event(lint).unwrap().1.kind, EventKind::Span(600, None));
+        assert_eq!(context.get_event(docs).unwrap().1.kind, EventKind::Span(600, Some(100)));
+    }
+
+    #[test]
+    fn test_typed_errors() {
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("worker")).unwrap();
+
+        assert!(matches!(
+            context.register_actor(Actor::new("worker")),
+            Err(Error::DuplicateActor(id)) if id == actor
+        ));
+
+        let span = |start, duration| Event::new(EventKind::Span(start, Some(duration)));
+        assert!(matches!(
+            context.add_event(&"nobody".to_owned(), span(0, 10)),
+            Err(Error::UnknownActor(id)) if id == "nobody"
+        ));
+        assert!(matches!(
+            context.add_event(&actor, span(i64::MAX, 10)),
+            Err(Error::InvalidEvent(_))
+        ));
+        assert!(matches!(
+            context.add_edge(0, 1),
+            Err(Error::UnknownEvent(0))
+        ));
+        assert!(matches!(
+            load_project("/tmp/project.yaml"),
+            Err(Error::UnknownProjectFormat(_))
+        ));
+        assert!(matches!(
+            load_project("/tmp/missing.chartr.json"),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(
+            save_project("/tmp/project.yaml", &RendererBuilder::default().build(), &context),
+            Err(Error::UnknownProjectFormat(_))
+        ));
+
+        // Import errors say which record failed without hiding why
+        let edge = r#"{"type": "edge", "from": 0, "to": 1}"#;
+        let records = import::read_json_lines(edge.as_bytes()).unwrap();
+        assert!(matches!(
+            import::apply(&mut context, records),
+            Err(Error::Record(Some(1), err)) if matches!(*err, Error::UnknownEvent(0))
+        ));
+        assert!(matches!(
+            import::read_json_lines("{".as_bytes()),
+            Err(Error::Import(_))
+        ));
+        assert!(matches!(chrome::read_trace("[".as_bytes()), Err(Error::Import(_))));
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("worker")).unwrap();
+        let first = context
+            .add_event(&actor, Event::new(EventKind::Span(0, Some(10))))
+            .unwrap();
+        let second = context
+            .add_event(&actor, Event::new(EventKind::Instant(20)))
+            .unwrap();
+        context.add_edge(first, second).unwrap();
+
+        let renderer = RendererBuilder::default().build();
+        let valid: serde_json::Value =
+            serde_json::from_str(&state::to_json(&renderer, &context).unwrap()).unwrap();
+        assert!(state::from_json(&valid.to_string()).is_ok());
+
+        // Hand edited charts are checked when loaded, not when rendered
+        let edited = |edit: fn(&mut serde_json::Value)| {
+            let mut document = valid.clone();
+            edit(&mut document);
+            state::from_json(&document.to_string())
+        };
+        for edit in [
+            |d: &mut serde_json::Value| d["events"]["actors"] = serde_json::json!({}),
+            |d: &mut serde_json::Value| d["events"]["events"]["worker"][1]["id"] = 0.into(),
+            |d: &mut serde_json::Value| d["events"]["next_event_id"] = 1.into(),
+            |d: &mut serde_json::Value| d["events"]["edges"][0]["to"] = 5.into(),
+        ] {
+            assert!(matches!(edited(edit), Err(Error::InvalidState(_))));
+        }
+
+        assert!(matches!(
+            context.get_actor(&"nobody".to_owned()),
+            Err(Error::UnknownActor(_))
+        ));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_render_png() {
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("worker")).unwrap();
+        context
+            .add_event(
+                &actor,
+                Event {
+                    id: None,
+                    fields: BTreeMap::new(),
+                    kind: EventKind::Span(0, Some(1_500_000)),
+                    value: "build".into(),
+                    tooltip: None,
+                },
+            )
+            .unwrap();
+
+        let r = RendererBuilder::default().heading("png").build();
+        let mut png = vec![];
+        r.render_to_png(&mut png, &context).unwrap();
+        std::fs::write("/tmp/chart.png", &png).unwrap();
+
+        let (r2, events) = load("/tmp/chart.png").unwrap();
+        assert_eq!(events.all_events().count(), 1);
+
+        // Doubling the resolution doubles the size of the image
+        let mut hidpi = vec![];
+        RendererBuilder::from(r2)
+            .dpi(Some(192.0))
+            .build()
+            .render_to_png(&mut hidpi, &events)
+            .unwrap();
+        let size = |png: &[u8]| {
+            let reader = ::png::Decoder::new(png).read_info().unwrap();
+            reader.info().size()
+        };
+        let (width, height) = size(&png);
+        assert_eq!(size(&hidpi), (width * 2, height * 2));
+    }
+
+    #[cfg(feature = "pdf")]
+    #[test]
+    fn test_render_pdf() {
+        let mut pdf = vec![];
+        RendererBuilder::default()
+            .heading("pdf")
+            .build()
+            .render_to_pdf(&mut pdf, &EventStore::default())
+            .unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
     }
 }
@@ -0,0 +1,86 @@
+use crate::error::{Error, Result};
+use serde_json::{json, Value};
+
+/// The schema version written by this version of chartr. Bump this and
+/// add a step to `STEPS` whenever the persisted state changes in a way
+/// `#[serde(default)]` can't paper over.
+pub const CURRENT_VERSION: u64 = 1;
+
+/// Upgrades a document from the version at its index to the next one
+const STEPS: [fn(Value) -> Result<Value>; CURRENT_VERSION as usize] = [v0_to_v1];
+
+/// The schema version of a persisted document. Documents from before
+/// versioning are version 0.
+pub fn version_of(document: &Value) -> Result<u64> {
+    match document {
+        Value::Array(_) => Ok(0),
+        Value::Object(fields) => fields
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| Error::InvalidState("no schema version".into())),
+        _ => Err(Error::InvalidState("not a recognized document".into())),
+    }
+}
+
+/// Upgrade a persisted document step by step to `CURRENT_VERSION`
+pub fn migrate(mut document: Value) -> Result<Value> {
+    let version = version_of(&document)?;
+    if version > CURRENT_VERSION {
+        return Err(Error::InvalidState(format!(
+            "schema version {} is newer than the supported version {}",
+            version, CURRENT_VERSION
+        )));
+    }
+
+    for step in STEPS.iter().skip(version as usize) {
+        document = step(document)?;
+    }
+    Ok(document)
+}
+
+/// Version 0 was an unversioned `[renderer, events]` tuple, and its
+/// events could predate ids. Those get ids after any already in use.
+fn v0_to_v1(document: Value) -> Result<Value> {
+    let not_a_pair = || Error::InvalidState("version 0 state is not a [renderer, events] pair".into());
+    let Value::Array(parts) = document else {
+        return Err(not_a_pair());
+    };
+    let Ok([renderer, mut events]) = <[Value; 2]>::try_from(parts) else {
+        return Err(not_a_pair());
+    };
+
+    let stored_next = events.get("next_event_id").and_then(Value::as_u64);
+    let mut all_events = events
+        .get_mut("events")
+        .and_then(Value::as_object_mut)
+        .into_iter()
+        .flat_map(|actors| actors.values_mut())
+        .filter_map(Value::as_array_mut)
+        .flatten()
+        .filter_map(Value::as_object_mut)
+        .collect::<Vec<_>>();
+
+    let mut next_event_id = all_events
+        .iter()
+        .filter_map(|event| event.get("id")?.as_u64())
+        .map(|id| id + 1)
+        .chain(stored_next)
+        .max()
+        .unwrap_or(0);
+    for event in all_events.iter_mut() {
+        if event.get("id").is_none_or(Value::is_null) {
+            event.insert("id".into(), json!(next_event_id));
+            next_event_id += 1;
+        }
+    }
+
+    if let Some(events) = events.as_object_mut() {
+        events.insert("next_event_id".into(), json!(next_event_id));
+    }
+
+    Ok(json!({
+        "version": 1,
+        "renderer": renderer,
+        "events": events,
+    }))
+}
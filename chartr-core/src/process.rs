@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::event::{Actor, Event, EventKind, EventStore};
+
+/// What we learn about a process from one read of `/proc/<pid>/stat`
+struct ProcStat {
+    ppid: u32,
+    comm: String,
+    zombie: bool,
+    /// The wait status, only meaningful once the process is a zombie
+    exit_code: Option<i32>,
+}
+
+fn read_stat(pid: u32) -> Option<ProcStat> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+
+    // The command name is in parentheses and may itself contain spaces
+    // or parentheses, so split around the last ')'
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let comm = stat[open + 1..close].to_owned();
+    let fields = stat[close + 1..].split_whitespace().collect::<Vec<_>>();
+
+    // Fields are numbered from 1 in proc(5), and `fields` starts at the
+    // third one (the state)
+    Some(ProcStat {
+        ppid: fields.get(1)?.parse().ok()?,
+        comm,
+        zombie: fields.first() == Some(&"Z"),
+        exit_code: fields.get(49).and_then(|code| code.parse().ok()),
+    })
+}
+
+fn read_cmdline(pid: u32) -> Option<String> {
+    let cmdline = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let args = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>();
+    (!args.is_empty()).then(|| args.join(" "))
+}
+
+fn read_processes() -> Result<BTreeMap<u32, ProcStat>> {
+    let mut processes = BTreeMap::new();
+    let entries = std::fs::read_dir("/proc").map_err(|err| {
+        std::io::Error::new(err.kind(), format!("Failed to read /proc: {err}"))
+    })?;
+    for entry in entries {
+        let Some(pid) = entry?.file_name().to_str().and_then(|pid| pid.parse().ok()) else {
+            continue;
+        };
+        // Processes can exit while we walk the directory
+        if let Some(stat) = read_stat(pid) {
+            processes.insert(pid, stat);
+        }
+    }
+    Ok(processes)
+}
+
+/// The current `CLOCK_MONOTONIC` time in microseconds, the same clock
+/// systemd's `*TimestampMonotonic` properties use. Charts keep their own
+/// origin on this clock, see `EventStore::monotonic_time`.
+pub fn monotonic_now() -> i64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_gettime only writes to the timespec we pass it
+    let result = unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    assert_eq!(result, 0, "Failed to read the monotonic clock");
+    now.tv_sec * 1_000_000 + now.tv_nsec / 1_000
+}
+
+/// Mark this process as a child subreaper, returning whether it already
+/// was one
+fn set_child_subreaper(enable: bool) -> Result<bool> {
+    let mut was_enabled: libc::c_int = 0;
+    // SAFETY: PR_GET_CHILD_SUBREAPER only writes to the int we pass it
+    if unsafe { libc::prctl(libc::PR_GET_CHILD_SUBREAPER, &mut was_enabled) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: PR_SET_CHILD_SUBREAPER only reads its integer argument
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, libc::c_ulong::from(enable)) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(was_enabled != 0)
+}
+
+/// Reap `pid` if it is an exited child of ours, returning its wait status
+fn reap(pid: u32) -> Option<i32> {
+    let mut status = 0;
+    // SAFETY: waitpid only writes to the status we pass it
+    let reaped = unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) };
+    (reaped == pid as libc::pid_t).then_some(status)
+}
+
+fn describe_wait_status(status: i32) -> String {
+    if status & 0x7f == 0 {
+        format!("exit status: {}", (status >> 8) & 0xff)
+    } else {
+        format!("killed by signal {}", status & 0x7f)
+    }
+}
+
+struct Traced {
+    first_seen: i64,
+    last_seen: i64,
+    comm: String,
+    cmdline: Option<String>,
+    exit: Option<String>,
+}
+
+/// The processes seen while tracing a command, ready to be recorded
+pub struct ProcessTree {
+    /// The `CLOCK_MONOTONIC` time the command started at, in microseconds
+    started: i64,
+    root: u32,
+    traced: BTreeMap<u32, Traced>,
+    still_running: BTreeMap<u32, ProcStat>,
+}
+
+/// Run `command` and record it and every process descended from it as an
+/// actor with a span from when it was first to last seen. Processes are
+/// found by polling `/proc` every `interval`, so anything that lives for
+/// less than that may be missed. Exit statuses of descendants are only
+/// known if a poll catches them before their parent reaps them, except for
+/// orphans, which we reap ourselves.
+///
+/// Times are on the chart's monotonic clock (see
+/// `EventStore::monotonic_time`), so commands run into the same chart one
+/// after another follow each other.
+pub fn run(command: &mut Command, interval: Duration, events: &mut EventStore) -> Result<ExitStatus> {
+    let (status, tree) = trace(command, interval)?;
+    tree.record(events)?;
+    Ok(status)
+}
+
+/// Like `run`, but without touching an `EventStore` until the caller
+/// records the returned tree
+pub fn trace(command: &mut Command, interval: Duration) -> Result<(ExitStatus, ProcessTree)> {
+    // As a subreaper, descendants orphaned by their parent exiting are
+    // reparented to us instead of init, so they stay in the tree
+    let was_subreaper = set_child_subreaper(true)?;
+    let result = trace_as_subreaper(command, interval);
+    if !was_subreaper {
+        set_child_subreaper(false)?;
+    }
+    result
+}
+
+fn trace_as_subreaper(
+    command: &mut Command,
+    interval: Duration,
+) -> Result<(ExitStatus, ProcessTree)> {
+    let started = monotonic_now();
+    let start = Instant::now();
+    let mut child = command.spawn().map_err(|err| {
+        std::io::Error::new(err.kind(), format!("Failed to start command: {err}"))
+    })?;
+    let root = child.id();
+    let us = std::process::id();
+
+    let mut traced: BTreeMap<u32, Traced> = BTreeMap::new();
+
+    let status = loop {
+        let now = start.elapsed().as_micros() as i64;
+        let processes = read_processes()?;
+
+        // Walk down from the root and from orphans, which are our children
+        // too. Also keep following anything seen before, in case it was
+        // reparented away from us.
+        let mut tree = BTreeSet::from([root]);
+        tree.extend(
+            processes
+                .iter()
+                .filter(|(_, stat)| stat.ppid == us)
+                .map(|(pid, _)| *pid),
+        );
+        let mut frontier = tree.iter().copied().collect::<Vec<_>>();
+        while let Some(parent) = frontier.pop() {
+            for (pid, stat) in processes.iter() {
+                if stat.ppid == parent && tree.insert(*pid) {
+                    frontier.push(*pid);
+                }
+            }
+        }
+        tree.extend(traced.keys().filter(|pid| processes.contains_key(pid)));
+
+        for pid in tree {
+            let Some(stat) = processes.get(&pid) else {
+                continue;
+            };
+
+            let entry = traced.entry(pid).or_insert_with(|| Traced {
+                first_seen: now,
+                last_seen: now,
+                comm: stat.comm.clone(),
+                cmdline: None,
+                exit: None,
+            });
+
+            entry.last_seen = now;
+            if stat.zombie {
+                // Orphans stay zombies until we reap them. The root is left
+                // for `try_wait` below.
+                let status = if pid != root && stat.ppid == us {
+                    reap(pid)
+                } else {
+                    None
+                };
+                entry.exit = status.or(stat.exit_code).map(describe_wait_status);
+            } else {
+                // The name and arguments change when the process execs
+                entry.comm = stat.comm.clone();
+                entry.cmdline = read_cmdline(pid).or(entry.cmdline.take());
+            }
+        }
+
+        if let Some(status) = child.try_wait()? {
+            if let Some(root) = traced.get_mut(&root) {
+                root.last_seen = start.elapsed().as_micros() as i64;
+                root.exit = Some(status.to_string());
+            }
+            break status;
+        }
+
+        std::thread::sleep(interval);
+    };
+
+    // Anything that outlived the command is shown as still running
+    let still_running = read_processes()?;
+
+    Ok((
+        status,
+        ProcessTree {
+            started,
+            root,
+            traced,
+            still_running,
+        },
+    ))
+}
+
+impl ProcessTree {
+    /// Add an actor with a span for each traced process
+    pub fn record(self, events: &mut EventStore) -> Result<()> {
+        let ProcessTree {
+            started,
+            root,
+            traced,
+            still_running,
+        } = self;
+        let offset = events.monotonic_time(started);
+
+        for (pid, process) in traced {
+            let running = pid != root && still_running.contains_key(&pid) && process.exit.is_none();
+
+            let actor = events.register_actor(Actor::new(format!("{} [{pid}]", process.comm)))?;
+
+            let mut tooltip = process.cmdline.unwrap_or(process.comm);
+            tooltip.push('\n');
+            tooltip.push_str(match (&process.exit, running) {
+                (Some(exit), _) => exit,
+                (None, true) => "still running",
+                (None, false) => "exit status: unknown",
+            });
+
+            let duration = (!running).then(|| (process.last_seen - process.first_seen) as u64);
+
+            events.add_event(
+                &actor,
+                Event {
+                    id: None,
+                    fields: BTreeMap::new(),
+                    kind: EventKind::Span(offset + process.first_seen, duration),
+                    value: "".into(),
+                    tooltip: Some(tooltip),
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::Write;
+use std::sync::OnceLock;
+
+#[cfg(feature = "png")]
+use resvg::usvg;
+#[cfg(all(feature = "pdf", not(feature = "png")))]
+use svg2pdf::usvg;
+
+use usvg::{fontdb, TreeParsing, TreePostProc};
+
+use crate::error::{Error, Result};
+
+/// The keyword of the PNG text chunk holding the chart state
+#[cfg(feature = "png")]
+pub(crate) const PNG_STATE_KEYWORD: &str = "chartr:state";
+
+/// The resolution svg pixels are defined at
+pub const CSS_DPI: f64 = 96.0;
+
+/// Fonts to draw text with when the ones the stylesheet asks for are
+/// missing, in order of preference
+const SANS_SERIF_FAMILIES: [&str; 5] = [
+    "Verdana",
+    "Helvetica",
+    "DejaVu Sans",
+    "Liberation Sans",
+    "Noto Sans",
+];
+
+/// Loading the system fonts is slow, so only do it once
+fn fonts() -> &'static fontdb::Database {
+    static FONTS: OnceLock<fontdb::Database> = OnceLock::new();
+    FONTS.get_or_init(|| {
+        let mut fonts = fontdb::Database::new();
+        fonts.load_system_fonts();
+
+        // The stylesheet falls back to sans-serif, which fontdb takes to
+        // mean Arial unless told otherwise
+        let families = fonts
+            .faces()
+            .flat_map(|face| face.families.iter().map(|(family, _)| family.clone()))
+            .collect::<Vec<_>>();
+        let sans_serif = SANS_SERIF_FAMILIES
+            .iter()
+            .map(|family| family.to_string())
+            .find(|family| families.contains(family))
+            .or_else(|| families.first().cloned());
+        if let Some(family) = sans_serif {
+            fonts.set_sans_serif_family(family);
+        }
+        fonts
+    })
+}
+
+/// Parse a rendered chart, with its text turned into paths as neither
+/// backend can draw text itself
+fn parse(svg: &str) -> Result<usvg::Tree> {
+    let mut tree = usvg::Tree::from_str(svg, &usvg::Options::default())
+        .map_err(|err| Error::Render(format!("Failed to parse rendered svg: {err}")))?;
+    tree.postprocess(
+        usvg::PostProcessingSteps {
+            convert_text_into_paths: true,
+        },
+        fonts(),
+    );
+    Ok(tree)
+}
+
+/// Rasterize `svg` on a white background, keeping `state` in a text chunk
+/// so the chart can be loaded again
+#[cfg(feature = "png")]
+pub(crate) fn write_png(svg: &str, dpi: f64, state: String, writer: impl Write) -> Result<()> {
+    use resvg::tiny_skia;
+
+    let tree = parse(svg)?;
+    let scale = (dpi / CSS_DPI) as f32;
+    let too_large = || Error::Render(format!("Chart is too large to rasterize at {dpi} dpi"));
+    let size = tree.size.to_int_size().scale_by(scale).ok_or_else(too_large)?;
+
+    let mut pixmap =
+        tiny_skia::Pixmap::new(size.width(), size.height()).ok_or_else(too_large)?;
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // The pixmap is premultiplied, but png wants straight alpha
+    let data = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect::<Vec<_>>();
+
+    let mut encoder = png::Encoder::new(writer, size.width(), size.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels_per_meter = (dpi / 0.0254).round() as u32;
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: png::Unit::Meter,
+    }));
+    encoder
+        .add_itxt_chunk(PNG_STATE_KEYWORD.into(), state)
+        .map_err(png_error)?;
+
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&data).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+    Ok(())
+}
+
+#[cfg(feature = "png")]
+fn png_error(err: png::EncodingError) -> Error {
+    match err {
+        png::EncodingError::IoError(err) => Error::Io(err),
+        err => Error::Render(err.to_string()),
+    }
+}
+
+/// Convert `svg` to a single page PDF sized as it would be printed at `dpi`
+#[cfg(feature = "pdf")]
+pub(crate) fn write_pdf(svg: &str, dpi: f64, mut writer: impl Write) -> Result<()> {
+    let tree = parse(svg)?;
+    let pdf = svg2pdf::convert_tree(
+        &tree,
+        svg2pdf::Options {
+            dpi: dpi as f32,
+            ..Default::default()
+        },
+    );
+    writer.write_all(&pdf)?;
+    writer.flush()?;
+    Ok(())
+}
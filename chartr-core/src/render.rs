@@ -1,12 +1,19 @@
-use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::hash::Hash;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::io::Write;
 use std::{path::Path, time::Duration};
 use svg::node::element as Svg;
 use svg::node::element::path::Data;
-use svg::Document;
+use svg::{Document, Node};
+use time::OffsetDateTime;
 
-use crate::event::{ActorId, EventKind, EventStore};
+use crate::error::Result;
+use crate::event::{ActorId, Epoch, Event, EventKind, EventStore};
+#[cfg(any(feature = "png", feature = "pdf"))]
+use crate::raster;
+use crate::{report, state, units};
 
 const APPROX_FONT_HEIGHT: f64 = 15.0;
 
@@ -45,31 +52,43 @@ impl svg::Node for ScriptComment {
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct RenderOpts {
-    us_per_line: u64,
-    sublines: u32,
-    us_per_pixel: u32,
+    us_per_line: NonZeroU64,
+    sublines: NonZeroU32,
+    us_per_pixel: NonZeroU32,
     pixels_per_actor: f64,
     actor_margin: f64,
     actor_name_padding: f64,
     top_margin: f64,
     side_margin: f64,
     heading: String,
+    #[serde(default)]
+    critical_path: bool,
+    #[serde(default)]
+    pack_lanes: bool,
+    #[serde(default)]
+    fit_width: Option<f64>,
+    #[serde(default)]
+    dpi: Option<f64>,
 }
 
 impl Default for RenderOpts {
     fn default() -> Self {
         Self {
-            us_per_line: Duration::from_secs(1).as_micros() as u64,
-            sublines: 10,
-            us_per_pixel: 10000,
+            us_per_line: NonZeroU64::new(Duration::from_secs(1).as_micros() as u64).unwrap(),
+            sublines: NonZeroU32::new(10).unwrap(),
+            us_per_pixel: NonZeroU32::new(10000).unwrap(),
             pixels_per_actor: 20.0,
             actor_margin: 0.5,
             actor_name_padding: 5.0,
             top_margin: 20.0,
             side_margin: 20.0,
             heading: "".into(),
+            critical_path: false,
+            pack_lanes: false,
+            fit_width: None,
+            dpi: None,
         }
     }
 }
@@ -84,42 +103,251 @@ impl RendererBuilder {
         Renderer { opts: self.opts }
     }
 
+    /// Text drawn above the chart, one line per line of `heading`
     pub fn heading(mut self, heading: impl AsRef<str>) -> Self {
         self.opts.heading = heading.as_ref().into();
         self
     }
+
+    /// Microseconds between labeled grid lines
+    pub fn us_per_line(mut self, us_per_line: NonZeroU64) -> Self {
+        self.opts.us_per_line = us_per_line;
+        self
+    }
+
+    /// Number of unlabeled grid lines between labeled ones
+    pub fn sublines(mut self, sublines: NonZeroU32) -> Self {
+        self.opts.sublines = sublines;
+        self
+    }
+
+    /// Horizontal resolution of the chart
+    pub fn us_per_pixel(mut self, us_per_pixel: NonZeroU32) -> Self {
+        self.opts.us_per_pixel = us_per_pixel;
+        self
+    }
+
+    /// Height of a single actor row (or lane)
+    pub fn pixels_per_actor(mut self, pixels_per_actor: f64) -> Self {
+        self.opts.pixels_per_actor = pixels_per_actor;
+        self
+    }
+
+    /// Vertical gap between an event and the edge of its row
+    pub fn actor_margin(mut self, actor_margin: f64) -> Self {
+        self.opts.actor_margin = actor_margin;
+        self
+    }
+
+    /// Horizontal gap between the actor name and its first event
+    pub fn actor_name_padding(mut self, actor_name_padding: f64) -> Self {
+        self.opts.actor_name_padding = actor_name_padding;
+        self
+    }
+
+    /// Pick the time resolution and grid lines from the data so the chart
+    /// is at most `width` pixels wide. This overrides `us_per_pixel`,
+    /// `us_per_line` and `sublines`.
+    pub fn fit_width(mut self, width: Option<f64>) -> Self {
+        self.opts.fit_width = width;
+        self
+    }
+
+    /// The resolution of PNG and PDF output. Defaults to 96, where one
+    /// image pixel is one svg pixel.
+    pub fn dpi(mut self, dpi: Option<f64>) -> Self {
+        self.opts.dpi = dpi;
+        self
+    }
+
+    /// Space above the heading
+    pub fn top_margin(mut self, top_margin: f64) -> Self {
+        self.opts.top_margin = top_margin;
+        self
+    }
+
+    /// Space left and right of the chart
+    pub fn side_margin(mut self, side_margin: f64) -> Self {
+        self.opts.side_margin = side_margin;
+        self
+    }
+
+    /// Highlight the critical path through the chart and summarize it
+    /// in the heading
+    pub fn critical_path(mut self, enabled: bool) -> Self {
+        self.opts.critical_path = enabled;
+        self
+    }
+
+    /// Stack overlapping events of an actor in separate lanes instead of
+    /// drawing them on top of each other
+    pub fn pack_lanes(mut self, enabled: bool) -> Self {
+        self.opts.pack_lanes = enabled;
+        self
+    }
 }
 
-#[derive(Deserialize, Serialize)]
+impl From<Renderer> for RendererBuilder {
+    fn from(renderer: Renderer) -> Self {
+        Self {
+            opts: renderer.opts,
+        }
+    }
+}
+
+/// The vertical placement of an actor's events
+struct Row {
+    y: f64,
+    /// The lane of each event, in the order of `EventStore::events_for`
+    lanes: Vec<usize>,
+}
+
+impl Row {
+    fn lane_count(&self) -> usize {
+        self.lanes.iter().max().map_or(1, |lane| lane + 1)
+    }
+
+    fn lane_y(&self, opts: &RenderOpts, index: usize) -> f64 {
+        self.y + self.lanes.get(index).copied().unwrap_or(0) as f64 * opts.pixels_per_actor
+    }
+}
+
+/// The minimum distance between labeled grid lines when fitting a width
+const MIN_PIXELS_PER_LABEL: f64 = 100.0;
+
+/// Find the smallest readable grid interval of at least `us` microseconds,
+/// i.e. 1, 2 or 5 times a power of ten in microseconds, minutes or hours.
+/// Returns the interval and a matching number of sublines.
+fn readable_interval(us: u64) -> (u64, u32) {
+    const MINUTE: u64 = 60_000_000;
+    const HOUR: u64 = 60 * MINUTE;
+
+    // Intervals past the end of a unit round up to the next unit instead
+    let (unit, limit) = if us <= MINUTE {
+        (1, MINUTE)
+    } else if us <= HOUR {
+        (MINUTE, HOUR)
+    } else {
+        (HOUR, u64::MAX)
+    };
+
+    let mut magnitude = 1u64;
+    loop {
+        for (leading, sublines) in [(1, 10), (2, 4), (5, 5)] {
+            let interval = (leading * magnitude).saturating_mul(unit).min(limit);
+            if interval >= us {
+                // Tiny intervals can't be evenly subdivided
+                let sublines = if interval.is_multiple_of(sublines as u64) {
+                    sublines
+                } else {
+                    1
+                };
+                return (interval, sublines);
+            }
+        }
+        magnitude = magnitude.saturating_mul(10);
+    }
+}
+
+#[derive(Deserialize, Serialize, Default)]
 pub struct Renderer {
     opts: RenderOpts,
 }
 
 impl Renderer {
     fn us_to_pixel(&self, us: i64) -> f64 {
-        us as f64 / self.opts.us_per_pixel as f64
+        us as f64 / self.opts.us_per_pixel.get() as f64
+    }
+
+    /// Assign each event of an actor to the first lane that is free by the
+    /// time the event starts. Without lane packing everything is in lane 0.
+    fn assign_lanes(&self, events: &EventStore, actor: &ActorId) -> Result<Vec<usize>> {
+        let mut lane_ends: Vec<Option<i64>> = vec![];
+        let mut lanes = vec![];
+
+        for event in events.events_for(actor)? {
+            if !self.opts.pack_lanes {
+                lanes.push(0);
+                continue;
+            }
+
+            // Endless spans never free their lane
+            let lane = lane_ends
+                .iter()
+                .position(|end| end.is_some_and(|end| end <= event.start_time()));
+
+            let lane = match lane {
+                Some(lane) => lane,
+                None => {
+                    lane_ends.push(None);
+                    lane_ends.len() - 1
+                }
+            };
+
+            lane_ends[lane] = event.end_time();
+            lanes.push(lane);
+        }
+
+        Ok(lanes)
     }
 
-    fn render_line_time(&self, us: i64) -> String {
-        // TODO: we probably shouldn't hard code this as seconds
-        let seconds = us as f64 / 1_000_000.0;
-        let fac = us as f64 % 1_000_000.0;
-        format!("{seconds}.{fac}")
+    /// The wall-clock time of time zero, if the chart has an epoch. Axis
+    /// labels and tooltips are then shown as wall-clock times.
+    fn epoch(events: &EventStore) -> Option<OffsetDateTime> {
+        events.epoch().map(Epoch::start)
+    }
+
+    fn render_line_time(&self, us: i64, epoch: Option<OffsetDateTime>) -> String {
+        match epoch {
+            Some(epoch) => units::format_wall_clock(epoch, us, self.opts.us_per_line.get()),
+            None => units::format_micros_scaled(us, self.opts.us_per_line.get()),
+        }
+    }
+
+    /// The spans to highlight as the critical path, if enabled
+    fn critical_path<'a>(&self, events: &'a EventStore) -> Vec<(&'a ActorId, &'a Event)> {
+        if self.opts.critical_path {
+            events.critical_path()
+        } else {
+            vec![]
+        }
+    }
+
+    /// The configured heading, followed by a summary of `critical`
+    fn heading_lines(&self, critical: &[(&ActorId, &Event)]) -> Vec<String> {
+        let mut lines = self
+            .opts
+            .heading
+            .lines()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+
+        if let (Some((_, first)), Some((_, last))) = (critical.first(), critical.last()) {
+            let mut actors = critical.iter().map(|(actor, _)| actor.as_str()).collect::<Vec<_>>();
+            actors.dedup();
+
+            let total = last.end_time().unwrap_or(last.start_time()) - first.start_time();
+            lines.push(format!(
+                "Critical path ({}): {}",
+                units::format_micros(total),
+                actors.join(" → ")
+            ));
+        }
+        lines
     }
 
-    fn calculate_heading_height(&self) -> f64 {
+    fn calculate_heading_height(&self, heading: &[String]) -> f64 {
         let heading_start = self.opts.top_margin + APPROX_FONT_HEIGHT;
-        let lines = self.opts.heading.lines().count() as f64;
-        let heading_end = heading_start + lines * APPROX_FONT_HEIGHT +
+        let lines = heading.len() as f64;
+        heading_start + lines * APPROX_FONT_HEIGHT +
             // Skip a couple of "lines" after the text of the heading
-            2.0 * APPROX_FONT_HEIGHT;
-        heading_end
+            2.0 * APPROX_FONT_HEIGHT
     }
 
-    fn render_heading(&self, mut output: Document) -> Result<Document> {
+    fn render_heading(&self, mut output: Document, heading: &[String]) -> Result<Document> {
         let mut current_y = self.opts.top_margin + APPROX_FONT_HEIGHT;
-        for line in self.opts.heading.lines() {
-            let text = Svg::Text::new(line)
+        for line in heading {
+            let text = Svg::Text::new(line.as_str())
                 .set("class", "heading")
                 .set("x", self.opts.side_margin)
                 .set("y", current_y);
@@ -133,47 +361,74 @@ impl Renderer {
     fn render_actor(
         &self,
         mut output: Svg::Group,
-        y: f64,
-        box_width: f64,
-        first_event_pixel: f64,
+        row: &Row,
+        last_event_pixel: f64,
         events: &EventStore,
         actor: ActorId,
+        critical: &[(&ActorId, &Event)],
     ) -> Result<Svg::Group> {
-        let mut g = Svg::Group::new().set("class", "actor");
+        let mut g = Svg::Group::new()
+            .set("class", "actor")
+            .set("data-actor", actor.clone());
 
-        let tooltip_prefix = events.get_actor(&actor).tooltip.clone();
+        let tooltip_prefix = events.get_actor(&actor)?.tooltip.clone();
+        let epoch = Self::epoch(events);
 
         let mut actor_start: Option<i64> = None;
-        for (i, event) in events
-            .events_for(&actor)
-            .with_context(|| "Failed to get actor events")?
-            .enumerate()
-        {
-            let (start, duration) = match event.kind {
-                EventKind::Span(start, duration) => (start, duration),
-                //TODO: handle instants
-                _ => unimplemented!(),
-            };
-
-            // Only draw the actor label at the start of the first span
+        for (i, event) in events.events_for(&actor)?.enumerate() {
+            // Only draw the actor label at the start of the first event
             if i == 0 {
-                actor_start = Some(start);
+                actor_start = Some(event.start_time());
             }
 
-            let width = match duration {
-                Some(duration) => self.us_to_pixel(duration as i64),
-                None => (first_event_pixel + box_width) - self.us_to_pixel(start),
+            let y = row.lane_y(&self.opts, i);
+
+            let height = self.opts.pixels_per_actor - 2.0 * self.opts.actor_margin;
+
+            let mut state: Svg::Element = match event.kind {
+                EventKind::Span(start, duration) => {
+                    let width = match duration {
+                        Some(duration) => self.us_to_pixel(duration as i64),
+                        None => last_event_pixel - self.us_to_pixel(start),
+                    };
+
+                    let class = if critical.iter().any(|(_, e)| std::ptr::eq(*e, event)) {
+                        "span critical"
+                    } else {
+                        "span"
+                    };
+
+                    Svg::Rectangle::new()
+                        .set("class", class)
+                        .set("width", width)
+                        .set("height", height)
+                        .set("x", self.us_to_pixel(start))
+                        .set("y", y + self.opts.actor_margin)
+                        .into()
+                }
+                EventKind::Instant(instant) => {
+                    // Instants are drawn as a diamond centered on the
+                    // instant and filling the height of the actor row
+                    let x = self.us_to_pixel(instant);
+                    let top = y + self.opts.actor_margin;
+                    let half = height / 2.0;
+                    let data = Data::new()
+                        .move_to((x, top))
+                        .line_to((x + half / 2.0, top + half))
+                        .line_to((x, top + height))
+                        .line_to((x - half / 2.0, top + half))
+                        .close();
+
+                    Svg::Path::new()
+                        .set("class", "instant")
+                        .set("d", data)
+                        .into()
+                }
             };
 
-            let mut state = Svg::Rectangle::new()
-                .set("class", "span")
-                .set("width", width)
-                .set(
-                    "height",
-                    self.opts.pixels_per_actor - 2.0 * self.opts.actor_margin,
-                )
-                .set("x", self.us_to_pixel(start))
-                .set("y", y + self.opts.actor_margin);
+            if let Some(id) = event.id {
+                state.assign("data-event-id", id);
+            }
 
             let attrs = state.get_attributes_mut();
             for (key, value) in event.fields.clone().into_iter() {
@@ -181,23 +436,38 @@ impl Renderer {
                 attrs.insert(key, format!("{value} {current}").into());
             }
 
-            if let Some(tip) = event
-                .tooltip
-                .as_ref()
-                .map(|tip| tooltip_prefix.clone().unwrap_or_default() + tip)
-            {
-                let tooltip = Svg::Title::new(tip);
-                state = state.add(tooltip);
+            // With a wall-clock epoch, tooltips also show when the event
+            // happened so it can be matched up with logs
+            let times = epoch.map(|epoch| {
+                let start = units::format_wall_clock(epoch, event.start_time(), 1000);
+                match event.kind {
+                    EventKind::Span(_, Some(_)) => {
+                        let end = event.end_time().unwrap_or_default();
+                        format!("{start} – {}", units::format_wall_clock(epoch, end, 1000))
+                    }
+                    EventKind::Span(_, None) => format!("{start} –"),
+                    EventKind::Instant(_) => start,
+                }
+            });
+
+            let tip = match (&event.tooltip, times) {
+                (Some(tip), Some(times)) => Some(format!("{tip}\n{times}")),
+                (Some(tip), None) => Some(tip.clone()),
+                (None, times) => times,
+            };
+
+            if let Some(tip) = tip.map(|tip| tooltip_prefix.clone().unwrap_or_default() + &tip) {
+                state.append(Svg::Title::new(tip));
             }
 
             g = g.add(state);
         }
 
         if let Some(start) = actor_start {
-            let actor_name = events.get_actor(&actor);
+            let actor_name = events.get_actor(&actor)?;
 
             let (class, padding) =
-                if self.us_to_pixel(start) < (first_event_pixel + box_width) / 2.0 {
+                if self.us_to_pixel(start) < last_event_pixel / 2.0 {
                     ("left", self.opts.actor_name_padding)
                 } else {
                     ("right", -self.opts.actor_name_padding)
@@ -208,7 +478,7 @@ impl Renderer {
                 .set("x", self.us_to_pixel(start) + padding)
                 // Assume the font is probably about 80% of the line
                 // height.
-                .set("y", y + self.opts.pixels_per_actor * 0.8);
+                .set("y", row.y + self.opts.pixels_per_actor * 0.8);
 
             g = g.add(text);
         }
@@ -223,13 +493,14 @@ impl Renderer {
         first_event_time: i64,
         last_event_time: i64,
         box_height: f64,
+        epoch: Option<OffsetDateTime>,
     ) -> Result<Svg::Group> {
-        let first_bar = first_event_time
-            - (first_event_time % self.opts.us_per_line as i64)
-            - self.opts.us_per_line as i64;
-        let last_bar = last_event_time + (last_event_time % self.opts.us_per_line as i64);
+        let us_per_line = self.opts.us_per_line.get();
+        let first_bar =
+            first_event_time - (first_event_time % us_per_line as i64) - us_per_line as i64;
+        let last_bar = last_event_time + (last_event_time % us_per_line as i64);
 
-        let step = self.opts.us_per_line as usize / self.opts.sublines as usize;
+        let step = (us_per_line as usize / self.opts.sublines.get() as usize).max(1);
         for x in (first_bar..=last_bar).step_by(step) {
             if x < first_event_time || x > last_event_time {
                 continue;
@@ -244,8 +515,8 @@ impl Renderer {
 
             let mut path = Svg::Path::new().set("d", data);
 
-            if x.unsigned_abs() % self.opts.us_per_line == 0 {
-                let text = Svg::Text::new(self.render_line_time(x))
+            if x.unsigned_abs() % us_per_line == 0 {
+                let text = Svg::Text::new(self.render_line_time(x, epoch))
                     .set("class", "label")
                     .set("x", scaled_x)
                     .set("y", -5);
@@ -260,24 +531,98 @@ impl Renderer {
         Ok(g)
     }
 
+    fn render_edges(
+        &self,
+        mut g: Svg::Group,
+        events: &EventStore,
+        rows: &BTreeMap<ActorId, Row>,
+    ) -> Result<Svg::Group> {
+        let center = self.opts.pixels_per_actor / 2.0;
+
+        for edge in events.edges() {
+            let (Some((from_actor, from)), Some((to_actor, to))) =
+                (events.get_event(edge.from), events.get_event(edge.to))
+            else {
+                continue;
+            };
+
+            // Actors without any events are not drawn, so there is no row
+            // to connect to
+            let (Some(from_row), Some(to_row)) = (rows.get(from_actor), rows.get(to_actor))
+            else {
+                continue;
+            };
+
+            let event_y = |row: &Row, actor, event| -> Result<f64> {
+                let index = events
+                    .events_for(actor)?
+                    .position(|e| std::ptr::eq(e, event))
+                    .unwrap_or(0);
+                Ok(row.lane_y(&self.opts, index))
+            };
+            let from_y = event_y(from_row, from_actor, from)?;
+            let to_y = event_y(to_row, to_actor, to)?;
+
+            let from_x = self.us_to_pixel(from.end_time().unwrap_or(from.start_time()));
+            let to_x = self.us_to_pixel(to.start_time());
+
+            let data = Data::new()
+                .move_to((from_x, from_y + center))
+                .line_to((to_x, to_y + center));
+
+            g = g.add(Svg::Path::new().set("class", "edge").set("d", data));
+        }
+
+        Ok(g)
+    }
+
     fn render_css(&self, document: Document) -> Result<Document> {
-        let defs = Svg::Definitions::new().add(Svg::Style::new(include_str!("assets/style.css")));
+        let arrow = Svg::Marker::new()
+            .set("id", "arrow")
+            .set("viewBox", "0 0 10 10")
+            .set("refX", 10)
+            .set("refY", 5)
+            .set("markerWidth", 6)
+            .set("markerHeight", 6)
+            .set("orient", "auto-start-reverse")
+            .add(Svg::Path::new().set("d", "M 0 0 L 10 5 L 0 10 z"));
+
+        let defs = Svg::Definitions::new()
+            .add(Svg::Style::new(include_str!("assets/style.css")))
+            .add(arrow);
         Ok(document.add(defs))
     }
 
-    pub fn render_script(&self, document: Document) -> Result<Document> {
+    pub fn render_script(
+        &self,
+        document: Document,
+        events: &EventStore,
+        heading: &[String],
+    ) -> Result<Document> {
+        let (first_event_time, _) = Self::time_range(events);
+        let left_offset = self.opts.side_margin - self.us_to_pixel(first_event_time);
+
+        let (epoch_ms, offset_ms) = match Self::epoch(events) {
+            Some(epoch) => (
+                (epoch.unix_timestamp_nanos() / 1_000_000).to_string(),
+                (epoch.offset().whole_seconds() as i64 * 1000).to_string(),
+            ),
+            None => ("null".into(), "0".into()),
+        };
+
         let script = include_str!("assets/script.js")
-            .replace("__LEFT_OFFSET__", &self.opts.side_margin.to_string())
+            .replace("__LEFT_OFFSET__", &left_offset.to_string())
+            .replace("__EPOCH_MS__", &epoch_ms)
+            .replace("__EPOCH_OFFSET_MS__", &offset_ms)
             .replace("__US_PER_PIXEL__", &self.opts.us_per_pixel.to_string())
             .replace(
                 "__HEADING_HEIGHT__",
-                &self.calculate_heading_height().to_string(),
+                &self.calculate_heading_height(heading).to_string(),
             );
         Ok(document.add(ScriptComment::new(script)))
     }
 
-    pub fn render(&self, path: impl AsRef<Path>, events: EventStore) -> Result<()> {
-        // First, determine how many lines we need
+    fn time_range(events: &EventStore) -> (i64, i64) {
         let first_event_time = events
             .all_events()
             .min_by_key(|e| e.start_time())
@@ -290,12 +635,110 @@ impl Renderer {
             })
             .unwrap_or(0);
 
-        let last_event_time = events
-            .all_events()
-            .filter_map(|e| e.end_time())
-            .max()
-            .unwrap_or(0);
+        // Open spans are drawn up to the latest time, which may be a start
+        let last_event_time = crate::blame::chart_end(events).unwrap_or(0);
+
+        (first_event_time, last_event_time)
+    }
 
+    /// A copy of this renderer with the time resolution and grid chosen so
+    /// the time range fits in `width` pixels
+    fn fitted(&self, width: f64, first_event_time: i64, last_event_time: i64) -> Renderer {
+        let range = (last_event_time - first_event_time).max(1) as f64;
+        let us_per_pixel = (range / width.max(1.0)).ceil().clamp(1.0, u32::MAX as f64);
+        let (us_per_line, sublines) =
+            readable_interval((us_per_pixel * MIN_PIXELS_PER_LABEL) as u64);
+
+        let mut opts = self.opts.clone();
+        opts.us_per_pixel = NonZeroU32::new(us_per_pixel as u32).unwrap_or(NonZeroU32::MIN);
+        opts.us_per_line = NonZeroU64::new(us_per_line).unwrap_or(NonZeroU64::MIN);
+        opts.sublines = NonZeroU32::new(sublines).unwrap_or(NonZeroU32::MIN);
+        Renderer { opts }
+    }
+
+    pub fn render(&self, path: impl AsRef<Path>, events: &EventStore) -> Result<()> {
+        let file = std::fs::File::create(path)?;
+        self.render_to_writer(std::io::BufWriter::new(file), events)
+    }
+
+    pub fn render_to_writer(&self, mut writer: impl Write, events: &EventStore) -> Result<()> {
+        svg::write(&mut writer, &self.document(events, true)?)?;
+        Ok(writer.flush()?)
+    }
+
+    pub fn render_to_string(&self, events: &EventStore) -> Result<String> {
+        Ok(self.document(events, true)?.to_string())
+    }
+
+    /// Render a standalone HTML report with the chart and tools to explore it
+    pub fn render_to_html(&self, writer: impl Write, events: &EventStore) -> Result<()> {
+        let critical = self.critical_path(events);
+        let title = self.heading_lines(&critical).into_iter().next();
+        report::write_html(
+            &self.document_with_path(events, false, &critical)?.to_string(),
+            title.as_deref().unwrap_or("chartr"),
+            events,
+            Self::epoch(events),
+            writer,
+        )
+    }
+
+    #[cfg(feature = "png")]
+    pub fn render_to_png(&self, writer: impl Write, events: &EventStore) -> Result<()> {
+        raster::write_png(
+            &self.document(events, false)?.to_string(),
+            self.dpi(),
+            state::to_json(self, events)?,
+            writer,
+        )
+    }
+
+    #[cfg(feature = "pdf")]
+    pub fn render_to_pdf(&self, writer: impl Write, events: &EventStore) -> Result<()> {
+        raster::write_pdf(&self.document(events, false)?.to_string(), self.dpi(), writer)
+    }
+
+    #[cfg(any(feature = "png", feature = "pdf"))]
+    fn dpi(&self) -> f64 {
+        self.opts.dpi.unwrap_or(raster::CSS_DPI)
+    }
+
+    /// The chart as an svg document. Non-interactive documents leave out
+    /// the hover script, for output that either can't run it or brings
+    /// its own.
+    fn document(&self, events: &EventStore, interactive: bool) -> Result<Document> {
+        self.document_with_path(events, interactive, &self.critical_path(events))
+    }
+
+    /// `document`, with the critical path already worked out
+    fn document_with_path(
+        &self,
+        events: &EventStore,
+        interactive: bool,
+        critical: &[(&ActorId, &Event)],
+    ) -> Result<Document> {
+        let (first, last) = Self::time_range(events);
+        match self.opts.fit_width {
+            // Fitting only changes the drawing. The chart keeps the options
+            // it was given, to be fitted afresh as it grows.
+            Some(width) => self
+                .fitted(width, first, last)
+                .render_scaled(self, events, first, last, interactive, critical),
+            None => self.render_scaled(self, events, first, last, interactive, critical),
+        }
+    }
+
+    /// Draw the chart at this renderer's scale, embedding the state of
+    /// `persisted`
+    fn render_scaled(
+        &self,
+        persisted: &Renderer,
+        events: &EventStore,
+        first_event_time: i64,
+        last_event_time: i64,
+        interactive: bool,
+        critical: &[(&ActorId, &Event)],
+    ) -> Result<Document> {
         // Gather the relevant actors for height calculation and such
         let mut actors = events
             .actors()
@@ -304,22 +747,35 @@ impl Renderer {
 
         actors.sort_by_key(|(_, event)| event.start_time());
 
-        let heading_height = self.calculate_heading_height();
+        let heading = self.heading_lines(critical);
+        let heading_height = self.calculate_heading_height(&heading);
 
         // TODO: consider heading width may be greater than box width
         let box_width = self.us_to_pixel(last_event_time - first_event_time);
-        let box_height = actors.len() as f64 * self.opts.pixels_per_actor;
+
+        // Rows may have several lanes, so lay them out before drawing
+        let mut rows = BTreeMap::new();
+        let mut box_height = 0.0;
+        for (actor, _) in actors.iter() {
+            let row = Row {
+                y: box_height,
+                lanes: self.assign_lanes(events, actor)?,
+            };
+            box_height += row.lane_count() as f64 * self.opts.pixels_per_actor;
+            rows.insert(actor.clone(), row);
+        }
 
         let mut document = Document::new()
             .set("width", box_width + 2.0 * self.opts.side_margin)
             .set("height", box_height + heading_height + self.opts.top_margin);
 
-        let serialized = svg::node::Comment::new(serde_json::to_string(&(self, &events))?);
-        document = document.add(serialized);
+        document = document.add(state::to_metadata(persisted, events)?);
 
-        document = self.render_script(document)?;
+        if interactive {
+            document = self.render_script(document, events, &heading)?;
+        }
         document = self.render_css(document)?;
-        document = self.render_heading(document)?;
+        document = self.render_heading(document, &heading)?;
 
         let start_x = self.opts.side_margin
             + if first_event_time < 0 {
@@ -332,42 +788,39 @@ impl Renderer {
             "transform",
             format!("translate({start_x}, {heading_height})"),
         );
-        g = self.render_lines(g, first_event_time, last_event_time, box_height)?;
+        g = self.render_lines(
+            g,
+            first_event_time,
+            last_event_time,
+            box_height,
+            Self::epoch(events),
+        )?;
 
-        let mut y = 0.0;
         for (actor, _) in actors.into_iter() {
-            g = self
-                .render_actor(
-                    g,
-                    y,
-                    box_width,
-                    self.us_to_pixel(first_event_time),
-                    &events,
-                    actor,
-                )
-                .with_context(|| "Failed to render actor events")?;
-
-            y += self.opts.pixels_per_actor;
+            g = self.render_actor(
+                g,
+                &rows[&actor],
+                self.us_to_pixel(first_event_time) + box_width,
+                events,
+                actor,
+                critical,
+            )?;
         }
 
+        g = self.render_edges(g, events, &rows)?;
+
         document = document
             .add(g)
             .add(
                 Svg::Rectangle::new()
                     .set("id", "indicator")
+                    // Shown once the mouse moves, and never in static output
+                    .set("visibility", "hidden")
                     .set("width", 1.0)
                     .set("height", box_height),
             )
             .add(Svg::Text::new("").set("id", "indicator-text"));
 
-        svg::save(path, &document).with_context(|| "Failed to save svg")
-    }
-}
-
-impl Default for Renderer {
-    fn default() -> Self {
-        Self {
-            opts: RenderOpts::default(),
-        }
+        Ok(document)
     }
 }
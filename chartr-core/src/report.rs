@@ -0,0 +1,227 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use time::OffsetDateTime;
+
+use crate::blame::{self, SortBy};
+use crate::error::Result;
+use crate::event::{ActorId, EventId, EventKind, EventStore};
+
+/// What the report's script knows about an event, beyond what is drawn
+#[derive(Serialize)]
+struct ReportEvent<'a> {
+    id: Option<EventId>,
+    actor: &'a ActorId,
+    start: i64,
+    end: Option<i64>,
+    instant: bool,
+    value: &'a str,
+    tooltip: Option<&'a str>,
+    fields: &'a BTreeMap<String, String>,
+    /// Events with an edge to this one
+    after: Vec<EventId>,
+    /// Events this one has an edge to
+    before: Vec<EventId>,
+}
+
+#[derive(Serialize)]
+struct ReportActor<'a> {
+    id: &'a ActorId,
+    tooltip: Option<&'a str>,
+}
+
+/// A row of the actor table. Open spans count up to the end of the chart,
+/// as they are drawn.
+#[derive(Serialize)]
+struct ReportSummary {
+    id: ActorId,
+    count: usize,
+    total: i64,
+    first: i64,
+    last: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportData<'a> {
+    epoch_ms: Option<i128>,
+    epoch_offset_ms: i64,
+    actors: Vec<ReportActor<'a>>,
+    events: Vec<ReportEvent<'a>>,
+    summaries: Vec<ReportSummary>,
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Fill in the placeholders of `template` in a single pass, so values
+/// that happen to contain a placeholder are left as they are
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some((at, placeholder, value)) = values
+        .iter()
+        .filter_map(|(placeholder, value)| Some((rest.find(placeholder)?, placeholder, value)))
+        .min_by_key(|(at, _, _)| *at)
+    {
+        output.push_str(&rest[..at]);
+        output.push_str(value);
+        rest = &rest[at + placeholder.len()..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Write an HTML page around the rendered `svg` chart of `events`, with
+/// everything the page needs inlined so it works offline
+pub(crate) fn write_html(
+    svg: &str,
+    title: &str,
+    events: &EventStore,
+    epoch: Option<OffsetDateTime>,
+    mut writer: impl Write,
+) -> Result<()> {
+    let edges = events.edges().collect::<Vec<_>>();
+
+    let summaries = blame::blame(events, SortBy::Total)
+        .into_iter()
+        .map(|blame| {
+            Ok(ReportSummary {
+                count: events.events_for(&blame.actor)?.count(),
+                id: blame.actor,
+                total: blame.total_us,
+                first: blame.first_start_us,
+                last: blame.last_end_us,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let data = ReportData {
+        epoch_ms: epoch.map(|epoch| epoch.unix_timestamp_nanos() / 1_000_000),
+        epoch_offset_ms: epoch.map_or(0, |epoch| epoch.offset().whole_seconds() as i64 * 1000),
+        actors: events
+            .actors()
+            .map(|id| {
+                let actor = events.get_actor(&id)?;
+                Ok(ReportActor {
+                    id: &actor.identity,
+                    tooltip: actor.tooltip.as_deref(),
+                })
+            })
+            .collect::<Result<_>>()?,
+        events: events
+            .actors()
+            .map(|id| Ok((&events.get_actor(&id)?.identity, events.events_for(&id)?)))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flat_map(|(actor, actor_events)| actor_events.map(move |event| (actor, event)))
+            .map(|(actor, event)| ReportEvent {
+                id: event.id,
+                actor,
+                start: event.start_time(),
+                end: event.end_time(),
+                instant: matches!(event.kind, EventKind::Instant(_)),
+                value: &event.value,
+                tooltip: event.tooltip.as_deref(),
+                fields: &event.fields,
+                after: edges
+                    .iter()
+                    .filter(|edge| Some(edge.to) == event.id)
+                    .map(|edge| edge.from)
+                    .collect(),
+                before: edges
+                    .iter()
+                    .filter(|edge| Some(edge.from) == event.id)
+                    .map(|edge| edge.to)
+                    .collect(),
+            })
+            .collect(),
+        summaries,
+    };
+
+    // The data sits in a script element, which only ends at "</"
+    let data = serde_json::to_string(&data)?.replace("</", "<\\/");
+
+    let html = fill(
+        include_str!("assets/report.html"),
+        &[
+            ("__TITLE__", &escape_html(title)),
+            ("__STYLE__", include_str!("assets/report.css")),
+            ("__SCRIPT__", include_str!("assets/report.js")),
+            ("__DATA__", &data),
+            ("__CHART__", svg),
+        ],
+    );
+
+    writer.write_all(html.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Actor, Event};
+    use crate::render::RendererBuilder;
+
+    #[test]
+    fn test_render_html() {
+        let mut context = EventStore::default();
+        let actor = context.register_actor(Actor::new("worker")).unwrap();
+        let span = |start, value: &str| Event {
+            fields: BTreeMap::from([("data-kind".into(), "</script>".into())]),
+            value: value.into(),
+            ..Event::new(EventKind::Span(start, Some(100)))
+        };
+        let first = context.add_event(&actor, span(0, "__CHART__")).unwrap();
+        let second = context.add_event(&actor, span(200, "second")).unwrap();
+        context.add_edge(first, second).unwrap();
+        // Open spans count up to the latest time, here their own start
+        let late = context.register_actor(Actor::new("late")).unwrap();
+        context
+            .add_event(&late, Event::new(EventKind::Span(500, None)))
+            .unwrap();
+
+        let r = RendererBuilder::default().heading("<Report>").build();
+        let mut html = vec![];
+        r.render_to_html(&mut html, &context).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        std::fs::write("/tmp/report.html", &html).unwrap();
+
+        assert!(html.contains("<title>&lt;Report&gt;</title>"));
+        assert!(html.contains(&format!("data-event-id=\"{second}\"")));
+
+        // Everything is inline, and event data can't end its script early
+        // or be mistaken for a placeholder
+        assert!(!html.contains("src="));
+        assert!(!html.contains("<link"));
+        assert_eq!(html.matches("</script>").count(), 2);
+        assert_eq!(html.matches("<svg").count(), 1);
+
+        let data = html.split_once(r#"<script id="chartr-data" type="application/json">"#);
+        let data = data.unwrap().1.split_once("</script>").unwrap().0;
+        let data: serde_json::Value = serde_json::from_str(&data.replace("<\\/", "</")).unwrap();
+        assert_eq!(
+            data["summaries"],
+            serde_json::json!([
+                {"id": "worker", "count": 2, "total": 200, "first": 0, "last": 300},
+                {"id": "late", "count": 1, "total": 0, "first": 500, "last": 500},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_fill() {
+        let values = [("__A__", "__B__"), ("__B__", "b")];
+        assert_eq!(fill("__B__ __A__ __A__", &values), "b __B__ __B__");
+        assert_eq!(fill("none", &values), "none");
+        assert_eq!(
+            escape_html("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+}
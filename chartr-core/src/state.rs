@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use svg::node::element as Svg;
+use svg::parser::Event;
+use svg::Node;
+
+use crate::error::{Error, Result};
+use crate::event::EventStore;
+use crate::migration;
+use crate::render::Renderer;
+
+/// The XML namespace of the element holding the chart state
+pub const NAMESPACE: &str = "https://github.com/ALSchwalm/chartr";
+
+const STATE_TAG: &str = "chartr:state";
+
+/// The bytes every png starts with
+pub(crate) const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Serialize)]
+struct PersistedRef<'a> {
+    version: u64,
+    renderer: &'a Renderer,
+    events: &'a EventStore,
+}
+
+#[derive(Deserialize)]
+struct Persisted {
+    renderer: Renderer,
+    events: EventStore,
+}
+
+/// Serialize the chart state at the current schema version
+pub(crate) fn to_json(renderer: &Renderer, events: &EventStore) -> Result<String> {
+    Ok(serde_json::to_string(&PersistedRef {
+        version: migration::CURRENT_VERSION,
+        renderer,
+        events,
+    })?)
+}
+
+/// Migrate a parsed chart state to the current schema version and check
+/// it holds together
+fn from_document(document: serde_json::Value) -> Result<(Renderer, EventStore)> {
+    let state: Persisted = serde_json::from_value(migration::migrate(document)?)?;
+    state.events.validate()?;
+    Ok((state.renderer, state.events))
+}
+
+/// Deserialize chart state of any supported schema version
+pub(crate) fn from_json(json: &str) -> Result<(Renderer, EventStore)> {
+    from_document(serde_json::from_str(json)?)
+}
+
+/// TOML has no null, so drop null fields and trailing null array entries
+/// (the duration of an open span) before writing it
+fn strip_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            fields.retain(|_, v| !v.is_null());
+            fields.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(items) => {
+            while items.last().is_some_and(|v| v.is_null()) {
+                items.pop();
+            }
+            items.iter_mut().for_each(strip_nulls);
+        }
+        _ => (),
+    }
+}
+
+/// Undo `strip_nulls` for the one place a missing value can't be
+/// defaulted: the kind of spans written without a duration
+fn restore_open_spans(document: &mut serde_json::Value) {
+    let Some(actors) = document
+        .pointer_mut("/events/events")
+        .and_then(serde_json::Value::as_object_mut)
+    else {
+        return;
+    };
+
+    let events = actors
+        .values_mut()
+        .filter_map(serde_json::Value::as_array_mut)
+        .flatten();
+    for event in events {
+        if let Some(serde_json::Value::Array(span)) = event.pointer_mut("/kind/Span") {
+            if span.len() == 1 {
+                span.push(serde_json::Value::Null);
+            }
+        }
+    }
+}
+
+/// Serialize the chart state as a TOML project file
+pub(crate) fn to_toml(renderer: &Renderer, events: &EventStore) -> Result<String> {
+    let mut document = serde_json::to_value(PersistedRef {
+        version: migration::CURRENT_VERSION,
+        renderer,
+        events,
+    })?;
+    strip_nulls(&mut document);
+    Ok(toml::to_string_pretty(&document)?)
+}
+
+/// Deserialize chart state from a TOML project file
+pub(crate) fn from_toml(content: &str) -> Result<(Renderer, EventStore)> {
+    let mut document: serde_json::Value = toml::from_str(content)?;
+    restore_open_spans(&mut document);
+    from_document(document)
+}
+
+/// Build the `<metadata>` element holding the serialized chart state
+pub(crate) fn to_metadata(renderer: &Renderer, events: &EventStore) -> Result<Svg::Element> {
+    let mut state = Svg::Element::new(STATE_TAG);
+    state.assign("xmlns:chartr", NAMESPACE);
+    // The text node escapes the markup characters in the json
+    state.append(svg::node::Text::new(to_json(renderer, events)?));
+
+    let mut metadata = Svg::Element::new("metadata");
+    metadata.append(state);
+    Ok(metadata)
+}
+
+/// Undo the escaping of xml character data
+fn unescape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|dec| dec.parse()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+
+        match decoded {
+            Some(c) => {
+                output.push(c);
+                rest = &rest[end + 1..];
+            }
+            // Not an entity we understand, so keep it as is
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Find the chart state in the contents of an svg. Charts from before the
+/// state moved into `<metadata>` kept it in the first comment.
+pub(crate) fn from_svg(content: &str) -> Result<(Renderer, EventStore)> {
+    let mut in_state = false;
+    let mut legacy = None;
+
+    for item in svg::read(content)? {
+        match item {
+            Event::Tag(STATE_TAG, svg::node::element::tag::Type::Start, _) => {
+                in_state = true;
+            }
+            Event::Text(text) if in_state => {
+                return from_json(&unescape(text.trim()));
+            }
+            Event::Tag(..) => in_state = false,
+            Event::Comment(c) if legacy.is_none() => {
+                // The svg crate keeps the added "<!-- " and " -->"
+                // text, so strip it before we deserialize
+                legacy = c
+                    .strip_prefix("<!--")
+                    .and_then(|c| c.strip_suffix("-->"))
+                    .map(str::trim);
+            }
+            _ => (),
+        }
+    }
+
+    match legacy {
+        Some(legacy) => from_json(legacy),
+        None => Err(Error::NoEmbeddedState),
+    }
+}
+
+/// Load the state kept in a text chunk of a png rendered by chartr
+#[cfg(feature = "png")]
+pub(crate) fn from_png(content: &[u8]) -> Result<(Renderer, EventStore)> {
+    let reader = png::Decoder::new(content)
+        .read_info()
+        .map_err(|err| Error::InvalidState(err.to_string()))?;
+
+    let chunk = reader
+        .info()
+        .utf8_text
+        .iter()
+        .find(|chunk| chunk.keyword == crate::raster::PNG_STATE_KEYWORD)
+        .ok_or(Error::NoEmbeddedState)?;
+    let json = chunk
+        .get_text()
+        .map_err(|err| Error::InvalidState(err.to_string()))?;
+    from_json(&json)
+}
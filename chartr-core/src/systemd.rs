@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use crate::error::{Error, Result};
+use crate::event::{Actor, Event, EventId, EventKind, EventStore};
+
+const FIRMWARE_COLOR: &str = "rgb(150,150,150)";
+const ACTIVATING_COLOR: &str = "rgb(255,0,0)";
+const ACTIVE_COLOR: &str = "rgb(200,150,150)";
+
+const UNIT_PROPERTIES: &str = "Id,InactiveExitTimestampMonotonic,ActiveEnterTimestampMonotonic,\
+ActiveExitTimestampMonotonic,InactiveEnterTimestampMonotonic,After,Requires";
+
+/// Properties of the service manager and its units, as printed by
+/// `systemctl show`
+#[derive(Debug, Default)]
+pub struct SystemdDump {
+    manager: BTreeMap<String, String>,
+    units: Vec<BTreeMap<String, String>>,
+}
+
+impl SystemdDump {
+    /// Parse `systemctl show` output. Blocks are separated by blank lines,
+    /// and the block without an `Id=` is the manager itself, so a dump can
+    /// be captured with:
+    ///
+    /// ```sh
+    /// (systemctl show; echo; systemctl show --all '*') > dump
+    /// ```
+    pub fn parse(dump: &str) -> Result<Self> {
+        let mut parsed = SystemdDump::default();
+
+        for block in dump.split("\n\n") {
+            let properties = block
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| {
+                    line.split_once('=')
+                        .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+                        .ok_or_else(|| Error::Import(format!("Invalid property line: {line}")))
+                })
+                .collect::<Result<BTreeMap<_, _>>>()?;
+
+            if properties.is_empty() {
+                continue;
+            }
+
+            if properties.contains_key("Id") {
+                parsed.units.push(properties);
+            } else {
+                parsed.manager.extend(properties);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Query the running service manager
+    pub fn capture() -> Result<Self> {
+        let systemctl = |args: &[&str]| -> Result<String> {
+            let output = Command::new("systemctl")
+                .args(args)
+                .output()
+                .map_err(|err| {
+                    std::io::Error::new(err.kind(), format!("Failed to run systemctl: {err}"))
+                })?;
+            if !output.status.success() {
+                return Err(Error::Import(format!(
+                    "systemctl {} failed: {}",
+                    args.join(" "),
+                    String::from_utf8_lossy(&output.stderr)
+                )));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+
+        let manager = systemctl(&["show"])?;
+        let units = systemctl(&["list-units", "--all", "--plain", "--no-legend", "--no-pager"])?;
+
+        let mut args = vec!["show", "-p", UNIT_PROPERTIES, "--"];
+        args.extend(units.lines().filter_map(|line| line.split_whitespace().next()));
+        let units = systemctl(&args)?;
+
+        Self::parse(&format!("{manager}\n\n{units}"))
+    }
+}
+
+/// A monotonic timestamp property, where 0 or a missing value means the
+/// transition never happened
+fn timestamp(properties: &BTreeMap<String, String>, name: &str) -> i64 {
+    properties
+        .get(&format!("{name}TimestampMonotonic"))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+}
+
+fn add_span(
+    events: &mut EventStore,
+    actor: &str,
+    start: i64,
+    end: i64,
+    color: &str,
+) -> Result<EventId> {
+    events.add_event(
+        &actor.to_owned(),
+        Event {
+            id: None,
+            fields: BTreeMap::from([("fill".into(), color.into())]),
+            kind: EventKind::Span(start, Some(end.saturating_sub(start).max(0) as u64)),
+            value: "".into(),
+            tooltip: None,
+        },
+    )
+}
+
+/// Add the boot stages and every unit that started activating before
+/// `default.target` was reached, with edges for their `After=` and
+/// `Requires=` dependencies
+pub fn import(dump: &SystemdDump, events: &mut EventStore) -> Result<()> {
+    let firmware = timestamp(&dump.manager, "Firmware");
+    let loader = timestamp(&dump.manager, "Loader");
+    let initrd = timestamp(&dump.manager, "InitRD");
+    let userspace = timestamp(&dump.manager, "Userspace");
+
+    // Systemd times count from boot, so events recorded as they happen
+    // should too, to line up with the units
+    if events.monotonic_origin().is_none() {
+        events.set_monotonic_origin(Some(0));
+    }
+
+    // Firmware and loader timestamps count backwards from the kernel start
+    let mut stage = |name: &str, start: i64, end: i64| -> Result<()> {
+        events.register_actor(Actor::new(name))?;
+        add_span(events, name, start, end, FIRMWARE_COLOR)?;
+        Ok(())
+    };
+
+    if firmware != 0 {
+        stage("firmware", -firmware, -loader)?;
+    }
+    if loader != 0 {
+        stage("loader", -loader, 0)?;
+    }
+
+    // Without an initrd the kernel runs until userspace starts
+    if initrd != 0 {
+        stage("kernel", 0, initrd)?;
+        stage("initrd", initrd, userspace)?;
+    } else {
+        stage("kernel", 0, userspace)?;
+    }
+
+    let default = dump
+        .units
+        .iter()
+        .find(|unit| unit["Id"] == "default.target")
+        .map(|unit| timestamp(unit, "ActiveEnter"))
+        .unwrap_or(i64::MAX);
+
+    let mut activations = BTreeMap::new();
+    for unit in dump.units.iter() {
+        let id = &unit["Id"];
+        let activating = timestamp(unit, "InactiveExit");
+        let activated = timestamp(unit, "ActiveEnter");
+        let mut deactivating = timestamp(unit, "ActiveExit");
+        let deactivated = timestamp(unit, "InactiveEnter");
+
+        // Skip anything that activated after the default target
+        if activating > default || activating == 0 {
+            continue;
+        }
+
+        events.register_actor(Actor::new(id))?;
+
+        // If the unit never actually activated, then the activating time
+        // ends when it deactivated and there is nothing else to show
+        if activated == 0 {
+            let span = add_span(events, id, activating, deactivated, ACTIVATING_COLOR)?;
+            activations.insert(id.clone(), span);
+            continue;
+        }
+
+        let span = add_span(events, id, activating, activated, ACTIVATING_COLOR)?;
+        activations.insert(id.clone(), span);
+
+        // If the unit never stopped being active, then it is "endless"
+        if deactivating == 0 || activated > deactivating {
+            events.add_event(
+                id,
+                Event {
+                    id: None,
+                    fields: BTreeMap::from([("fill".into(), ACTIVE_COLOR.into())]),
+                    kind: EventKind::Span(activated, None),
+                    value: "".into(),
+                    tooltip: None,
+                },
+            )?;
+        } else {
+            deactivating = deactivating.min(default);
+            add_span(events, id, activated, deactivating, ACTIVE_COLOR)?;
+        }
+    }
+
+    // Units wait on their dependencies to finish activating
+    for unit in dump.units.iter() {
+        let Some(&to) = activations.get(&unit["Id"]) else {
+            continue;
+        };
+
+        let mut dependencies = ["After", "Requires"]
+            .iter()
+            .filter_map(|property| unit.get(*property))
+            .flat_map(|value| value.split_whitespace())
+            .filter_map(|dependency| activations.get(dependency).copied())
+            .collect::<Vec<_>>();
+        dependencies.sort();
+        dependencies.dedup();
+
+        for from in dependencies {
+            events.add_edge(from, to)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_systemd() {
+        let dump = "Version=255
+FirmwareTimestampMonotonic=3000000
+LoaderTimestampMonotonic=1000000
+InitRDTimestampMonotonic=500000
+UserspaceTimestampMonotonic=2000000
+
+Id=default.target
+InactiveExitTimestampMonotonic=4000000
+ActiveEnterTimestampMonotonic=5000000
+ActiveExitTimestampMonotonic=0
+InactiveEnterTimestampMonotonic=0
+After=network.service
+
+Id=network.service
+InactiveExitTimestampMonotonic=2500000
+ActiveEnterTimestampMonotonic=3500000
+ActiveExitTimestampMonotonic=0
+InactiveEnterTimestampMonotonic=0
+After=udev.service
+Requires=udev.service
+
+Id=udev.service
+InactiveExitTimestampMonotonic=2100000
+ActiveEnterTimestampMonotonic=2400000
+ActiveExitTimestampMonotonic=6000000
+InactiveEnterTimestampMonotonic=6100000
+
+Id=late.service
+InactiveExitTimestampMonotonic=7000000
+ActiveEnterTimestampMonotonic=7500000
+
+Id=never.service
+InactiveExitTimestampMonotonic=0
+";
+
+        let dump = SystemdDump::parse(dump).unwrap();
+        let mut context = EventStore::default();
+        import(&dump, &mut context).unwrap();
+
+        let actors = context.actors().collect::<Vec<_>>();
+        assert_eq!(
+            actors,
+            vec![
+                "default.target",
+                "firmware",
+                "initrd",
+                "kernel",
+                "loader",
+                "network.service",
+                "udev.service"
+            ]
+        );
+
+        let spans = |actor: &str| {
+            context
+                .events_for(&actor.to_owned())
+                .unwrap()
+                .map(|e| (e.start_time(), e.end_time()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spans("firmware"), vec![(-3_000_000, Some(-1_000_000))]);
+        assert_eq!(spans("initrd"), vec![(500_000, Some(2_000_000))]);
+
+        // Still active at the end, so the active span is open
+        assert_eq!(
+            spans("network.service"),
+            vec![(2_500_000, Some(3_500_000)), (3_500_000, None)]
+        );
+
+        // Deactivated after the default target, so clamp to it
+        assert_eq!(
+            spans("udev.service"),
+            vec![(2_100_000, Some(2_400_000)), (2_400_000, Some(5_000_000))]
+        );
+
+        // udev -> network -> default.target
+        let edge_actors = context
+            .edges()
+            .map(|edge| {
+                (
+                    context.get_event(edge.from).unwrap().0.as_str(),
+                    context.get_event(edge.to).unwrap().0.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edge_actors,
+            vec![
+                ("network.service", "default.target"),
+                ("udev.service", "network.service")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_systemd_without_initrd() {
+        let dump = "LoaderTimestampMonotonic=0
+UserspaceTimestampMonotonic=2000000
+
+Id=failed.service
+InactiveExitTimestampMonotonic=2100000
+ActiveEnterTimestampMonotonic=0
+InactiveEnterTimestampMonotonic=2300000
+";
+
+        let dump = SystemdDump::parse(dump).unwrap();
+        let mut context = EventStore::default();
+        import(&dump, &mut context).unwrap();
+
+        // Without an initrd or loader, the kernel runs until userspace
+        assert_eq!(
+            context.actors().collect::<Vec<_>>(),
+            vec!["failed.service", "kernel"]
+        );
+        let kernel = context.events_for(&"kernel".to_owned()).unwrap().next().unwrap();
+        assert_eq!(kernel.kind, EventKind::Span(0, Some(2_000_000)));
+
+        // A unit that never activated only has its activating span
+        let failed = context
+            .events_for(&"failed.service".to_owned())
+            .unwrap()
+            .map(|e| &e.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(failed, vec![&EventKind::Span(2_100_000, Some(200_000))]);
+
+        assert!(SystemdDump::parse("Id=a.service\nnot a property").is_err());
+    }
+}
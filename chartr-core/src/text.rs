@@ -0,0 +1,336 @@
+use std::io::Write;
+
+use crate::error::{Error, Result};
+use crate::blame::{busy_time, chart_end};
+use crate::event::{ActorId, Event, EventKind, EventStore};
+use crate::units;
+
+/// Actor names longer than this are cut short to leave room for the bars
+const MAX_NAME_WIDTH: usize = 32;
+
+/// The bars never get narrower than this, even in a narrow terminal
+const MIN_BAR_WIDTH: usize = 10;
+
+/// Room for the total duration after each bar
+const DURATION_WIDTH: usize = 9;
+
+/// Blocks filling 1/8 to 8/8 of a cell from the left
+const LEFT_BLOCKS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+
+/// A quick text view of a chart, drawing each actor as a row of block
+/// characters, like `systemd-analyze blame` with a timeline
+pub struct TextRenderer {
+    width: usize,
+    color: bool,
+}
+
+/// How much of one cell of a row is covered, and by what
+#[derive(Clone, Default)]
+struct Cell {
+    coverage: f64,
+    /// Whether the covered part is at the left of the cell, i.e. a span
+    /// ends in it rather than starts in it
+    from_left: bool,
+    instant: bool,
+    /// The fill of the event covering the most of the cell
+    fill: Option<(f64, (u8, u8, u8))>,
+}
+
+impl TextRenderer {
+    /// Render to fit in `width` columns
+    pub fn new(width: usize) -> Self {
+        TextRenderer {
+            width,
+            color: false,
+        }
+    }
+
+    /// Color the bars with the `fill` field of their events, using ANSI
+    /// escapes
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn render_to_string(&self, events: &EventStore) -> Result<String> {
+        let mut output = vec![];
+        self.render_to_writer(&mut output, events)?;
+        String::from_utf8(output).map_err(|err| Error::Render(err.to_string()))
+    }
+
+    pub fn render_to_writer(&self, mut writer: impl Write, events: &EventStore) -> Result<()> {
+        let mut actors = events
+            .actors()
+            .filter_map(|actor| {
+                let first = events.events_for(&actor).ok()?.next()?.start_time();
+                Some((first, actor))
+            })
+            .collect::<Vec<_>>();
+        actors.sort();
+
+        let Some(first) = events.all_events().map(Event::start_time).min() else {
+            return Ok(());
+        };
+        let last = chart_end(events).unwrap_or(first);
+
+        let name_width = actors
+            .iter()
+            .map(|(_, actor)| actor.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(MAX_NAME_WIDTH);
+        let bar_width = self
+            .width
+            .saturating_sub(name_width + DURATION_WIDTH + 4)
+            .max(MIN_BAR_WIDTH);
+
+        // Open spans run to the end, as in the svg
+        let us_per_cell = (last - first).max(1) as f64 / bar_width as f64;
+
+        let label = |us| match events.epoch() {
+            Some(epoch) => units::format_wall_clock(epoch.start(), us, 1000),
+            None => units::format_micros(us),
+        };
+        let (start_label, end_label) = (label(first), label(last));
+        writeln!(
+            writer,
+            "{:name_width$}  {start_label}{end_label:>width$}",
+            "",
+            width = bar_width.saturating_sub(start_label.chars().count()),
+        )?;
+
+        for (_, actor) in actors {
+            let cells = self.cells(events, &actor, first, last, us_per_cell, bar_width)?;
+
+            let mut name = actor.chars().take(name_width).collect::<String>();
+            if actor.chars().count() > name_width {
+                name.pop();
+                name.push('…');
+            }
+
+            write!(writer, "{name:name_width$} │")?;
+            self.write_bar(&mut writer, &cells)?;
+            writeln!(
+                writer,
+                "│ {:>DURATION_WIDTH$}",
+                units::format_micros(busy_time(events, &actor, last))
+            )?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn cells(
+        &self,
+        events: &EventStore,
+        actor: &ActorId,
+        first: i64,
+        last: i64,
+        us_per_cell: f64,
+        bar_width: usize,
+    ) -> Result<Vec<Cell>> {
+        let mut cells = vec![Cell::default(); bar_width];
+        let position = |us: i64| (us - first) as f64 / us_per_cell;
+
+        for event in events.events_for(actor)? {
+            let fill = event.fields.get("fill").and_then(|fill| parse_color(fill));
+
+            let (start, end) = match event.kind {
+                EventKind::Instant(instant) => {
+                    let cell = (position(instant) as usize).min(bar_width - 1);
+                    cells[cell].instant = true;
+                    if let Some(fill) = fill {
+                        cells[cell].fill.get_or_insert((0.0, fill));
+                    }
+                    continue;
+                }
+                EventKind::Span(start, _) => {
+                    (position(start), position(event.end_time().unwrap_or(last)))
+                }
+            };
+
+            let from = (start.floor() as usize).min(bar_width - 1);
+            let to = (end.ceil() as usize).clamp(from + 1, bar_width);
+            for (i, cell) in cells.iter_mut().enumerate().take(to).skip(from) {
+                let left = start.max(i as f64);
+                let right = end.min(i as f64 + 1.0);
+                // Zero length spans still show up as a sliver
+                let covered = (right - left).max(0.125);
+
+                cell.coverage = (cell.coverage + covered).min(1.0);
+                cell.from_left = left <= i as f64;
+                if let Some(fill) = fill {
+                    if cell.fill.is_none_or(|(most, _)| covered > most) {
+                        cell.fill = Some((covered, fill));
+                    }
+                }
+            }
+        }
+        Ok(cells)
+    }
+
+    fn write_bar(&self, writer: &mut impl Write, cells: &[Cell]) -> Result<()> {
+        let mut current = None;
+        for cell in cells {
+            let block = if cell.instant {
+                '◆'
+            } else if cell.coverage <= 0.0 {
+                ' '
+            } else if cell.from_left {
+                LEFT_BLOCKS[((cell.coverage * 8.0).round() as usize).clamp(1, 8) - 1]
+            } else if cell.coverage >= 0.75 {
+                // There are only half and eighth blocks on the right
+                '█'
+            } else if cell.coverage >= 0.25 {
+                '▐'
+            } else {
+                '▕'
+            };
+
+            if self.color {
+                let fill = cell.fill.map(|(_, fill)| fill);
+                if fill != current {
+                    match fill {
+                        Some((r, g, b)) => write!(writer, "\x1b[38;2;{r};{g};{b}m")?,
+                        None => write!(writer, "\x1b[0m")?,
+                    }
+                    current = fill;
+                }
+            }
+            write!(writer, "{block}")?;
+        }
+        if self.color && current.is_some() {
+            write!(writer, "\x1b[0m")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse the css colors charts commonly use: `#rgb`, `#rrggbb`,
+/// `rgb(r,g,b)` and a few names
+fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    let color = color.trim().to_ascii_lowercase();
+
+    if let Some(hex) = color.strip_prefix('#') {
+        let digit = |i: usize| u8::from_str_radix(hex.get(i..i + 1)?, 16).ok();
+        let byte = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return match hex.len() {
+            3 => Some((digit(0)? * 17, digit(1)? * 17, digit(2)? * 17)),
+            6 => Some((byte(0)?, byte(2)?, byte(4)?)),
+            _ => None,
+        };
+    }
+
+    if let Some(args) = color.strip_prefix("rgb(").and_then(|c| c.strip_suffix(')')) {
+        let mut parts = args.split(',').map(|part| part.trim().parse::<u8>().ok());
+        let rgb = (parts.next()??, parts.next()??, parts.next()??);
+        return parts.next().is_none().then_some(rgb);
+    }
+
+    Some(match color.as_str() {
+        "black" => (0, 0, 0),
+        "white" => (255, 255, 255),
+        "gray" | "grey" => (128, 128, 128),
+        "red" => (255, 0, 0),
+        "green" => (0, 128, 0),
+        "blue" => (0, 0, 255),
+        "yellow" => (255, 255, 0),
+        "orange" => (255, 165, 0),
+        "purple" => (128, 0, 128),
+        "cyan" => (0, 255, 255),
+        "magenta" => (255, 0, 255),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Actor;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_render_text() {
+        let mut context = EventStore::default();
+        let build = context.register_actor(Actor::new("build")).unwrap();
+        let deploy = context.register_actor(Actor::new("deploy")).unwrap();
+        let event = |kind, fill: Option<&str>| Event {
+            fields: fill
+                .map(|fill| BTreeMap::from([("fill".into(), fill.into())]))
+                .unwrap_or_default(),
+            ..Event::new(kind)
+        };
+        for e in [
+            event(EventKind::Span(0, Some(600)), Some("#AB7C94")),
+            event(EventKind::Span(400, Some(400)), None),
+            event(EventKind::Instant(900), None),
+        ] {
+            context.add_event(&build, e).unwrap();
+        }
+        context
+            .add_event(&deploy, event(EventKind::Span(800, None), Some("rgb(0, 128, 0)")))
+            .unwrap();
+
+        let text = TextRenderer::new(60).render_to_string(&context).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].trim_start().starts_with("0µs"));
+        assert!(lines[0].ends_with("900µs"));
+        for line in &lines[1..] {
+            assert_eq!(line.chars().count(), 60);
+        }
+
+        // Overlapping spans are counted once, and open ones run to the end
+        assert!(lines[1].starts_with("build  │█"));
+        assert!(lines[1].contains('◆'));
+        assert!(lines[1].ends_with("800µs"));
+        assert!(lines[2].ends_with("100µs"));
+        assert!(!text.contains('\x1b'));
+
+        let colored = TextRenderer::new(60)
+            .color(true)
+            .render_to_string(&context)
+            .unwrap();
+        assert!(colored.contains("\x1b[38;2;171;124;148m"));
+        assert!(colored.contains("\x1b[38;2;0;128;0m"));
+    }
+
+    #[test]
+    fn test_render_text_narrow() {
+        let mut context = EventStore::default();
+        let long = "a".repeat(MAX_NAME_WIDTH + 10);
+        for name in [long.as_str(), "b"] {
+            let actor = context.register_actor(Actor::new(name)).unwrap();
+            context
+                .add_event(&actor, Event::new(EventKind::Span(0, Some(1000))))
+                .unwrap();
+        }
+
+        // Long names are cut short, and the bars keep a minimum width even
+        // if that overflows the terminal
+        let text = TextRenderer::new(5).render_to_string(&context).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        let expected_width = MAX_NAME_WIDTH + MIN_BAR_WIDTH + DURATION_WIDTH + 4;
+        assert!(lines[1].starts_with(&format!("{}… │", "a".repeat(MAX_NAME_WIDTH - 1))));
+        assert!(lines[2].starts_with(&format!("b{} │", " ".repeat(MAX_NAME_WIDTH - 1))));
+        for line in &lines[1..] {
+            assert_eq!(line.chars().count(), expected_width);
+            assert!(line.contains(&"█".repeat(MIN_BAR_WIDTH)));
+        }
+
+        assert_eq!(TextRenderer::new(80).render_to_string(&EventStore::default()).unwrap(), "");
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#AB7C94"), Some((171, 124, 148)));
+        assert_eq!(parse_color(" #fff "), Some((255, 255, 255)));
+        assert_eq!(parse_color("rgb(0, 128,255)"), Some((0, 128, 255)));
+        assert_eq!(parse_color("Orange"), Some((255, 165, 0)));
+
+        for invalid in ["#abcd", "#ggg", "rgb(1,2)", "rgb(1,2,3,4)", "rgb(256,0,0)", "teal", ""] {
+            assert_eq!(parse_color(invalid), None, "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use time::macros::format_description;
+use time::{Duration, OffsetDateTime};
+
+const MILLISECOND: u64 = 1_000;
+const SECOND: u64 = 1_000_000;
+const MINUTE: u64 = 60 * SECOND;
+const HOUR: u64 = 60 * MINUTE;
+
+/// Pick the largest unit that is not bigger than `us`. This uses the same
+/// thresholds as `renderMicros` in the svg script.
+fn unit_for(us: u64) -> (u64, &'static str) {
+    if us < MILLISECOND {
+        (1, "µs")
+    } else if us < SECOND {
+        (MILLISECOND, "ms")
+    } else if us < MINUTE {
+        (SECOND, "s")
+    } else if us < HOUR {
+        (MINUTE, "min")
+    } else {
+        (HOUR, "h")
+    }
+}
+
+/// Format a number of microseconds in a unit chosen from `scale`, e.g.
+/// the grid interval, so that neighbouring labels share a unit
+pub fn format_micros_scaled(us: i64, scale: u64) -> String {
+    let (unit, suffix) = unit_for(scale);
+    format!("{}{suffix}", us as f64 / unit as f64)
+}
+
+/// Format a number of microseconds in the most readable unit
+pub fn format_micros(us: i64) -> String {
+    format_micros_scaled(us, us.unsigned_abs())
+}
+
+/// Format the wall-clock time `us` microseconds after `epoch`, with as
+/// much precision as `scale` needs. Times the calendar can't represent
+/// fall back to the offset from the epoch.
+pub fn format_wall_clock(epoch: OffsetDateTime, us: i64, scale: u64) -> String {
+    let Some(time) = epoch.checked_add(Duration::microseconds(us)) else {
+        return format_micros(us);
+    };
+
+    let formatted = if scale >= MINUTE {
+        time.format(format_description!("[hour]:[minute]"))
+    } else if scale >= SECOND {
+        time.format(format_description!("[hour]:[minute]:[second]"))
+    } else if scale >= MILLISECOND {
+        time.format(format_description!(
+            "[hour]:[minute]:[second].[subsecond digits:3]"
+        ))
+    } else {
+        time.format(format_description!(
+            "[hour]:[minute]:[second].[subsecond digits:6]"
+        ))
+    };
+
+    formatted.unwrap_or_else(|_| format_micros(us))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_micros() {
+        assert_eq!(format_micros_scaled(1_500_000, 500_000), "1500ms");
+        assert_eq!(format_micros_scaled(1_500_000, 1_000_000), "1.5s");
+        assert_eq!(format_micros_scaled(-2_000_000, 1_000_000), "-2s");
+        assert_eq!(format_micros_scaled(90 * 60_000_000, 30 * 60_000_000), "90min");
+        assert_eq!(format_micros(0), "0µs");
+        assert_eq!(format_micros(250), "250µs");
+        assert_eq!(format_micros(-1500), "-1.5ms");
+        assert_eq!(format_micros(7_200_000_000), "2h");
+    }
+
+    #[test]
+    fn test_format_wall_clock() {
+        let epoch = time::macros::datetime!(2024-01-02 03:04:05 UTC);
+        assert_eq!(format_wall_clock(epoch, 1_500_000, 2 * MINUTE), "03:04");
+        assert_eq!(format_wall_clock(epoch, 1_500_000, 1_000_000), "03:04:06");
+        assert_eq!(format_wall_clock(epoch, 1_500_000, 100_000), "03:04:06.500");
+        assert_eq!(format_wall_clock(epoch, 1, 10), "03:04:05.000001");
+        assert_eq!(format_wall_clock(epoch, -1_000_000, SECOND), "03:04:04");
+
+        // Times past the end of the calendar fall back to the offset
+        assert_eq!(format_wall_clock(epoch, i64::MAX, SECOND), format_micros(i64::MAX));
+    }
+}
@@ -0,0 +1,176 @@
+//! Keeping concurrent commands on the same chart from losing each other's
+//! updates or leaving it half-written
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long to wait between attempts to take a contended lock
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An exclusive advisory lock on a chart, released when dropped
+pub struct ChartLock {
+    _file: File,
+}
+
+/// The sidecar file that is locked in place of the chart. The chart itself
+/// can't be locked because `replace` swaps in a new file, and a waiter
+/// holding the old one would go on to read stale state. The sidecar is left
+/// behind afterwards, as removing it would race with anyone about to lock it.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+impl ChartLock {
+    /// Lock the chart at `path`, retrying for up to `timeout` while some
+    /// other process holds it
+    pub fn acquire(path: &Path, timeout: Duration) -> io::Result<ChartLock> {
+        let lock_path = lock_path(path);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            // SAFETY: flock only acts on the descriptor, which `file` keeps open
+            let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+            if result == 0 {
+                return Ok(ChartLock { _file: file });
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "gave up after {}s waiting for another command to release {}",
+                        timeout.as_secs_f64(),
+                        lock_path.display()
+                    ),
+                ));
+            }
+            std::thread::sleep(RETRY_INTERVAL);
+        }
+    }
+}
+
+/// Write a new version of `path` by handing `write` a temporary path next
+/// to it, then renaming that over the original so readers only ever see
+/// the old or the new chart. The temporary name keeps the extension, as
+/// that decides how project files are written, and the new chart keeps
+/// the permissions of the old one.
+pub fn replace<E: From<io::Error>>(
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut name = std::ffi::OsString::from(format!(".{}.", std::process::id()));
+    name.push(path.file_name().unwrap_or_default());
+    let temp = path.with_file_name(name);
+
+    let result = write(&temp).and_then(|_| {
+        File::open(&temp)?.sync_all()?;
+        match std::fs::metadata(path) {
+            Ok(metadata) => std::fs::set_permissions(&temp, metadata.permissions())?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+        std::fs::rename(&temp, path)?;
+
+        // The rename itself only survives a crash once the directory is synced
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_lock_timeout() {
+        let path = Path::new("/tmp/lock-timeout.svg");
+        let held = ChartLock::acquire(path, Duration::ZERO).unwrap();
+
+        let started = Instant::now();
+        let Err(err) = ChartLock::acquire(path, Duration::from_millis(200)) else {
+            panic!("Locked a chart that was already locked");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        drop(held);
+        ChartLock::acquire(path, Duration::ZERO).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let path = Path::new("/tmp/lock-counter.txt");
+        std::fs::write(path, "0").unwrap();
+
+        // Each writer reads the count and writes it back incremented, which
+        // loses updates unless the lock keeps them apart
+        let writers = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        let _lock = ChartLock::acquire(path, Duration::from_secs(10)).unwrap();
+                        let count: u64 = std::fs::read_to_string(path).unwrap().parse().unwrap();
+                        replace(path, |temp| std::fs::write(temp, (count + 1).to_string()))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "40");
+    }
+
+    #[test]
+    fn test_replace() {
+        let path = Path::new("/tmp/lock-replace.txt");
+        let _ = std::fs::remove_file(path);
+
+        replace(path, |temp| std::fs::write(temp, "new")).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "new");
+
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        replace(path, |temp| std::fs::write(temp, "newer")).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "newer");
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+
+        // A failed write leaves the old chart and no temporary file behind
+        let mut temp_path = None;
+        let result = replace(path, |temp| {
+            std::fs::write(temp, "partial")?;
+            temp_path = Some(temp.to_owned());
+            Err(io::Error::other("failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "newer");
+        assert!(!temp_path.unwrap().exists());
+    }
+}
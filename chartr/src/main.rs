@@ -1,28 +1,187 @@
-use chartr_core::{event, load, render};
+use anyhow::{Context, Result};
+use chartr_core::{
+    blame, chrome, event, import, load, load_from_reader, load_project, process, render, save_project,
+    systemd, text, ProjectFormat,
+};
 use clap::{Args, Parser, Subcommand};
-use std::path::PathBuf;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+mod lock;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = EXIT_CODES)]
 struct Cli {
     /// What mode to run the program in
     #[command(subcommand)]
     mode: Command,
 
+    /// A rendered svg chart, or a .chartr.json/.chartr.toml project file.
+    /// "-" reads an svg from stdin and writes any changes to stdout.
     path: PathBuf,
+
+    /// How many seconds to wait for other commands updating the same chart
+    #[arg(long, global = true, default_value_t = 30)]
+    lock_timeout: u64,
 }
 
 #[derive(Clone, Debug, Subcommand)]
 enum Command {
     Create(CreateArgs),
+    Configure(RenderArgs),
     AddActor(AddActorArgs),
     AddEvent(AddEventArgs),
+    AddEdge(AddEdgeArgs),
+    SetEpoch(SetEpochArgs),
+    /// Rewrite a chart using the current schema version
+    Upgrade,
+    /// Render a chart or project to an svg
+    Render(RenderOutputArgs),
+    /// Add many actors, events and edges at once, rendering only once
+    ///
+    /// Edges can name events by id, or by the key an event record earlier in
+    /// the same import gave them.
+    Import(ImportArgs),
+    /// Add the boot stages and units of the running system, or of a dump
+    /// of `systemctl show` output
+    ImportSystemd(ImportSystemdArgs),
+    /// Write the chart's events in another format
+    Export(ExportArgs),
+    /// Run a command and chart the time spent in it and its descendants
+    Run(RunArgs),
+    /// Start a span now, to be closed by `end`
+    Begin(BeginArgs),
+    /// Close the latest span started by `begin` with the same label
+    End(EndArgs),
+    /// Add an instant at the current time
+    Mark(MarkArgs),
+    /// Draw the chart in the terminal
+    Show(ShowArgs),
+    /// List the actors by the time they spent in spans, like
+    /// `systemd-analyze blame`
+    Blame(BlameArgs),
 }
 
 #[derive(Args, Clone, Debug)]
 struct CreateArgs {
+    #[command(flatten)]
+    render: RenderArgs,
+}
+
+/// Options controlling how a chart is drawn. Anything not given keeps
+/// its current (or default) value.
+#[derive(Args, Clone, Debug)]
+struct RenderArgs {
     #[arg(long)]
     heading: Option<String>,
+
+    /// Highlight the critical path through the chart
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    critical_path: Option<bool>,
+
+    /// Stack overlapping events of an actor in separate lanes
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pack_lanes: Option<bool>,
+
+    /// Scale the chart to fit in this many pixels, overriding the
+    /// resolution and grid options. Zero turns fitting off again.
+    #[arg(long)]
+    width: Option<f64>,
+
+    /// Label the time axis with wall-clock times, taking this RFC 3339
+    /// timestamp as time zero. This sets the chart's epoch like set-epoch,
+    /// and "none" removes it.
+    #[arg(long, value_parser = parse_epoch)]
+    epoch: Option<Epoch>,
+
+    /// Microseconds between labeled grid lines
+    #[arg(long)]
+    us_per_line: Option<NonZeroU64>,
+
+    /// Number of unlabeled grid lines between labeled ones
+    #[arg(long)]
+    sublines: Option<NonZeroU32>,
+
+    /// Horizontal resolution of the chart
+    #[arg(long)]
+    us_per_pixel: Option<NonZeroU32>,
+
+    /// Height of a single actor row
+    #[arg(long)]
+    pixels_per_actor: Option<f64>,
+
+    /// Vertical gap between an event and the edge of its row
+    #[arg(long)]
+    actor_margin: Option<f64>,
+
+    /// Horizontal gap between an actor name and its first event
+    #[arg(long)]
+    actor_name_padding: Option<f64>,
+
+    #[arg(long)]
+    top_margin: Option<f64>,
+
+    #[arg(long)]
+    side_margin: Option<f64>,
+
+    /// The resolution of png and pdf output. Zero goes back to the
+    /// default of 96, one image pixel per svg pixel.
+    #[arg(long)]
+    dpi: Option<f64>,
+}
+
+impl RenderArgs {
+    /// The epoch is kept with the events, so it's set on the chart rather
+    /// than the renderer
+    fn apply_epoch(&self, events: &mut event::EventStore) {
+        if let Some(Epoch(epoch)) = self.epoch {
+            events.set_epoch(epoch.map(event::Epoch::new))
+        }
+    }
+
+    fn apply(self, mut builder: render::RendererBuilder) -> render::RendererBuilder {
+        if let Some(heading) = self.heading {
+            builder = builder.heading(heading)
+        }
+        if let Some(enabled) = self.critical_path {
+            builder = builder.critical_path(enabled)
+        }
+        if let Some(enabled) = self.pack_lanes {
+            builder = builder.pack_lanes(enabled)
+        }
+        if let Some(width) = self.width {
+            builder = builder.fit_width(Some(width).filter(|w| *w > 0.0))
+        }
+        if let Some(us) = self.us_per_line {
+            builder = builder.us_per_line(us)
+        }
+        if let Some(sublines) = self.sublines {
+            builder = builder.sublines(sublines)
+        }
+        if let Some(us) = self.us_per_pixel {
+            builder = builder.us_per_pixel(us)
+        }
+        if let Some(pixels) = self.pixels_per_actor {
+            builder = builder.pixels_per_actor(pixels)
+        }
+        if let Some(margin) = self.actor_margin {
+            builder = builder.actor_margin(margin)
+        }
+        if let Some(padding) = self.actor_name_padding {
+            builder = builder.actor_name_padding(padding)
+        }
+        if let Some(margin) = self.top_margin {
+            builder = builder.top_margin(margin)
+        }
+        if let Some(margin) = self.side_margin {
+            builder = builder.side_margin(margin)
+        }
+        if let Some(dpi) = self.dpi {
+            builder = builder.dpi(Some(dpi).filter(|dpi| *dpi > 0.0))
+        }
+        builder
+    }
 }
 
 #[derive(Args, Clone, Debug)]
@@ -36,8 +195,15 @@ struct AddActorArgs {
 #[derive(Args, Clone, Debug)]
 struct AddEventArgs {
     actor: String,
-    start: i64,
-    duration: Option<u32>,
+
+    /// Microseconds relative to time zero, or an RFC 3339 timestamp if the
+    /// chart has an epoch
+    #[arg(value_parser = parse_start, allow_hyphen_values = true)]
+    start: Start,
+
+    /// The duration of the event in microseconds
+    #[arg(value_parser = parse_duration, allow_hyphen_values = true)]
+    duration: Option<u64>,
 
     #[arg(short, long, default_value = "false")]
     endless: bool,
@@ -49,41 +215,525 @@ struct AddEventArgs {
     tooltip: Option<String>,
 }
 
-fn main() {
-    let cli = Cli::parse();
+#[derive(Args, Clone, Debug)]
+struct AddEdgeArgs {
+    /// The id of the event the dependency starts from
+    from: event::EventId,
 
-    match cli.mode {
-        Command::Create(args) => {
-            let mut builder = render::RendererBuilder::default();
+    /// The id of the event that depends on `from`
+    to: event::EventId,
+}
+
+#[derive(Args, Clone, Debug)]
+struct RenderOutputArgs {
+    /// Where to write the chart, or "-" for stdout
+    output: PathBuf,
+
+    /// The output format. Defaults to the one matching the output's
+    /// extension, or svg.
+    #[arg(long)]
+    format: Option<RenderFormat>,
+
+    /// The resolution of png and pdf output, for this render only
+    #[arg(long, value_parser = parse_dpi)]
+    dpi: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum RenderFormat {
+    /// An interactive svg
+    Svg,
+    /// A standalone page with the chart, search, filters and an actor summary
+    Html,
+    /// A static image, which can be loaded again like an svg
+    #[cfg(feature = "png")]
+    Png,
+    /// A single page document
+    #[cfg(feature = "pdf")]
+    Pdf,
+}
+
+impl RenderFormat {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html" | "htm") => RenderFormat::Html,
+            #[cfg(feature = "png")]
+            Some("png") => RenderFormat::Png,
+            #[cfg(feature = "pdf")]
+            Some("pdf") => RenderFormat::Pdf,
+            _ => RenderFormat::Svg,
+        }
+    }
+
+    fn write(
+        self,
+        renderer: &render::Renderer,
+        writer: impl std::io::Write,
+        events: &event::EventStore,
+    ) -> Result<()> {
+        match self {
+            RenderFormat::Svg => renderer.render_to_writer(writer, events),
+            RenderFormat::Html => renderer.render_to_html(writer, events),
+            #[cfg(feature = "png")]
+            RenderFormat::Png => renderer.render_to_png(writer, events),
+            #[cfg(feature = "pdf")]
+            RenderFormat::Pdf => renderer.render_to_pdf(writer, events),
+        }?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ImportFormat {
+    /// One json record per line
+    Jsonl,
+    /// Comma separated records with a header row
+    Csv,
+    /// The Chrome Trace Event Format used by Chrome, Perfetto and others
+    ChromeTrace,
+}
+
+#[derive(Args, Clone, Debug)]
+struct ImportArgs {
+    /// The file to read records from. Reads stdin if not given or "-".
+    input: Option<PathBuf>,
+
+    /// The record format. Defaults to csv for .csv files, chrome-trace for
+    /// input that starts like a trace, and jsonl otherwise.
+    #[arg(short, long)]
+    format: Option<ImportFormat>,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ExportFormat {
+    /// The Chrome Trace Event Format used by Chrome, Perfetto and others
+    ChromeTrace,
+}
+
+#[derive(Args, Clone, Debug)]
+struct ExportArgs {
+    /// The file to write to. Writes to stdout if not given or "-".
+    output: Option<PathBuf>,
+
+    #[arg(short, long, default_value = "chrome-trace")]
+    format: ExportFormat,
+}
+
+#[derive(Args, Clone, Debug)]
+struct BeginArgs {
+    /// The actor to add the span to, registered if it doesn't exist yet
+    actor: String,
+    label: String,
+
+    #[arg(short, long)]
+    color: Option<String>,
+
+    #[arg(short, long, allow_hyphen_values = true)]
+    tooltip: Option<String>,
+}
+
+#[derive(Args, Clone, Debug)]
+struct EndArgs {
+    actor: String,
+    label: String,
+}
+
+#[derive(Args, Clone, Debug)]
+struct MarkArgs {
+    /// The actor to add the instant to, registered if it doesn't exist yet
+    actor: String,
+
+    #[arg(short, long)]
+    color: Option<String>,
+
+    #[arg(short, long, allow_hyphen_values = true)]
+    tooltip: Option<String>,
+}
+
+#[derive(Args, Clone, Debug)]
+struct ShowArgs {
+    /// Columns to fit the chart in. Defaults to the width of the terminal.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    width: Option<u16>,
+
+    /// Color events with their fill
+    #[arg(long, default_value = "auto")]
+    color: ColorChoice,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum ColorChoice {
+    /// When writing to a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Args, Clone, Debug)]
+struct BlameArgs {
+    #[arg(short, long, default_value = "total")]
+    sort: BlameSort,
+
+    #[arg(short, long, default_value = "plain")]
+    format: BlameFormat,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum BlameSort {
+    /// Longest time in spans first, with overlapping spans counted once
+    Total,
+    /// Earliest first event first
+    FirstStart,
+    /// Latest last event first
+    LastEnd,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum BlameFormat {
+    /// The sort value and the actor, one per line
+    Plain,
+    /// An array of summaries, with times in microseconds
+    Json,
+    /// A header row then one row per actor, with times in microseconds
+    Csv,
+}
+
+#[derive(Args, Clone, Debug)]
+struct RunArgs {
+    /// How often to look for new and exited processes, in milliseconds
+    #[arg(long, default_value = "10")]
+    interval: u64,
+
+    #[arg(trailing_var_arg = true, required = true)]
+    command: Vec<String>,
+}
+
+#[derive(Args, Clone, Debug)]
+struct ImportSystemdArgs {
+    /// Read unit properties from a saved dump instead of systemctl
+    #[arg(long)]
+    dump: Option<PathBuf>,
+}
+
+#[derive(Args, Clone, Debug)]
+struct SetEpochArgs {
+    /// The RFC 3339 wall-clock time of time zero, "now", or "none" to
+    /// remove the epoch
+    #[arg(value_parser = parse_epoch)]
+    time: Epoch,
+
+    /// The UTC offset to display times in, e.g. "+02:00". Defaults to the
+    /// offset of the epoch.
+    #[arg(long, value_parser = parse_offset, allow_hyphen_values = true)]
+    timezone: Option<time::UtcOffset>,
+}
 
-            if let Some(heading) = args.heading {
-                builder = builder.heading(heading)
+#[derive(Clone, Debug)]
+enum Start {
+    Relative(i64),
+    WallClock(time::OffsetDateTime),
+}
+
+impl Start {
+    fn micros(&self, events: &event::EventStore) -> chartr_core::Result<i64> {
+        match self {
+            Start::Relative(us) => Ok(*us),
+            Start::WallClock(time) => {
+                let epoch = events.epoch().ok_or_else(|| {
+                    chartr_core::Error::InvalidEvent(
+                        "Chart has no epoch, set one with set-epoch first".into(),
+                    )
+                })?;
+                epoch.micros_until(*time)
             }
+        }
+    }
+}
+
+fn parse_start(arg: &str) -> Result<Start, String> {
+    if let Ok(us) = arg.parse() {
+        return Ok(Start::Relative(us));
+    }
+
+    time::OffsetDateTime::parse(arg, &time::format_description::well_known::Rfc3339)
+        .map(Start::WallClock)
+        .map_err(|_| format!("'{arg}' is neither microseconds nor an RFC 3339 timestamp"))
+}
+
+fn parse_offset(arg: &str) -> Result<time::UtcOffset, String> {
+    let format = time::format_description::parse("[offset_hour sign:mandatory]:[offset_minute]")
+        .expect("Invalid offset format");
+    time::UtcOffset::parse(arg, &format)
+        .map_err(|_| format!("'{arg}' is not a UTC offset like +02:00"))
+}
+
+#[derive(Clone, Debug)]
+struct Epoch(Option<time::OffsetDateTime>);
+
+fn parse_epoch(arg: &str) -> Result<Epoch, String> {
+    if arg == "none" {
+        return Ok(Epoch(None));
+    }
+
+    if arg == "now" {
+        return Ok(Epoch(Some(time::OffsetDateTime::now_utc())));
+    }
+
+    time::OffsetDateTime::parse(arg, &time::format_description::well_known::Rfc3339)
+        .map(|epoch| Epoch(Some(epoch)))
+        .map_err(|e| format!("'{arg}' is not an RFC 3339 timestamp: {e}"))
+}
+
+fn parse_duration(arg: &str) -> Result<u64, String> {
+    let duration: i128 = arg
+        .parse()
+        .map_err(|_| format!("'{arg}' is not a whole number of microseconds"))?;
+
+    if duration < 0 {
+        return Err(format!("duration must not be negative (got {duration})"));
+    }
+
+    // Durations are added to i64 start times, so they must fit in one
+    i64::try_from(duration)
+        .map(|d| d as u64)
+        .map_err(|_| format!("duration must be at most {}us (got {duration})", i64::MAX))
+}
+
+fn parse_dpi(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(dpi) if dpi.is_finite() && dpi > 0.0 => Ok(dpi),
+        _ => Err(format!("'{arg}' is not a positive resolution")),
+    }
+}
+
+/// The width of the terminal on stdout, falling back to $COLUMNS and then
+/// 80 columns when it isn't one
+fn terminal_width() -> usize {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes to the winsize we pass it
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_col > 0 {
+        return size.ws_col as usize;
+    }
+
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(80)
+}
+
+/// Add an event at the current time, registering the actor if needed
+fn add_now(
+    events: &mut event::EventStore,
+    actor: String,
+    kind: event::EventKind,
+    value: String,
+    color: Option<String>,
+    tooltip: Option<String>,
+) -> chartr_core::Result<()> {
+    if !events.contains_actor(&actor) {
+        events.register_actor(event::Actor::new(&actor))?;
+    }
+
+    let mut fields = std::collections::BTreeMap::default();
+    if let Some(color) = color {
+        fields.insert("fill".into(), color);
+    }
+
+    events
+        .add_event(
+            &actor,
+            event::Event {
+                id: None,
+                fields,
+                value,
+                kind,
+                tooltip,
+            },
+        )?;
+    Ok(())
+}
+
+/// Whether `path` is "-", meaning stdin or stdout
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Load a chart from either a project file or a rendered svg
+fn open(path: &Path) -> Result<(render::Renderer, event::EventStore)> {
+    if is_stdio(path) {
+        return load_from_reader(std::io::stdin().lock())
+            .with_context(|| "Failed to load chart from stdin");
+    }
+
+    match ProjectFormat::from_path(path) {
+        Some(_) => load_project(path),
+        None => load(path),
+    }
+    .with_context(|| format!("Failed to load {}", path.display()))
+}
+
+/// Write a chart back in the same form it was loaded from
+fn save(path: &Path, renderer: render::Renderer, events: event::EventStore) -> Result<()> {
+    if is_stdio(path) {
+        return Ok(renderer.render_to_writer(std::io::stdout().lock(), &events)?);
+    }
+
+    lock::replace(path, |temp| match ProjectFormat::from_path(temp) {
+        Some(_) => Ok(save_project(temp, &renderer, &events)?),
+        None => match RenderFormat::from_path(temp) {
+            RenderFormat::Html => {
+                anyhow::bail!("Charts can't be kept as html, render one from an svg chart instead")
+            }
+            #[cfg(feature = "pdf")]
+            RenderFormat::Pdf => {
+                anyhow::bail!("Charts can't be kept as pdf, render one from an svg chart instead")
+            }
+            format => {
+                let file = std::fs::File::create(temp)?;
+                format.write(&renderer, std::io::BufWriter::new(file), &events)
+            }
+        },
+    })
+    .with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// Lock the chart for the rest of the command
+fn lock(path: &Path, timeout_secs: u64) -> Result<lock::ChartLock> {
+    let timeout = std::time::Duration::from_secs(timeout_secs);
+    lock::ChartLock::acquire(path, timeout)
+        .with_context(|| format!("Failed to lock {}", path.display()))
+}
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  1  Any other error
+  2  Invalid arguments
+  3  Unknown actor
+  4  Unknown event
+  5  Actor already registered
+  6  Invalid event, e.g. ending a span that isn't open
+  7  Not a chart, a chart from a newer version of chartr, or input that
+     can't be imported
+  8  Failed to read or write a file
+  9  Timed out waiting for another command using the chart
+ 10  Failed to render the chart, e.g. one too large for a png";
+
+/// The exit code of a core error, as listed in `EXIT_CODES`
+fn core_exit_code(err: &chartr_core::Error) -> u8 {
+    use chartr_core::Error;
+
+    match err {
+        Error::UnknownActor(_) => 3,
+        Error::UnknownEvent(_) => 4,
+        Error::DuplicateActor(_) => 5,
+        Error::InvalidEvent(_) => 6,
+        Error::NoEmbeddedState
+        | Error::InvalidState(_)
+        | Error::UnknownProjectFormat(_)
+        | Error::Import(_) => 7,
+        Error::Io(_) => 8,
+        Error::Render(_) => 10,
+        // The record's position doesn't change what went wrong
+        Error::Record(_, err) => core_exit_code(err),
+    }
+}
+
+/// The exit code for `err`, as listed in `EXIT_CODES`
+fn exit_code(err: &anyhow::Error) -> ExitCode {
+    let code = err.chain().find_map(|cause| {
+        if let Some(err) = cause.downcast_ref::<chartr_core::Error>() {
+            return Some(core_exit_code(err));
+        }
+        cause.downcast_ref::<std::io::Error>().map(|err| match err.kind() {
+            std::io::ErrorKind::TimedOut => 9,
+            _ => 8,
+        })
+    });
+    ExitCode::from(code.unwrap_or(1))
+}
 
-            let renderer = builder.build();
-            let store = event::EventStore::default();
-            renderer.render(cli.path, store).unwrap();
+impl Command {
+    /// Whether the command rewrites the chart, and so has to hold its lock
+    /// from loading it until the new version is in place
+    fn mutates(&self) -> bool {
+        // `run` isn't listed as it takes the lock itself once the command
+        // has exited, so that the command can update the chart meanwhile
+        matches!(
+            self,
+            Command::Create(_)
+                | Command::Configure(_)
+                | Command::AddActor(_)
+                | Command::AddEvent(_)
+                | Command::AddEdge(_)
+                | Command::SetEpoch(_)
+                | Command::Upgrade
+                | Command::Import(_)
+                | Command::ImportSystemd(_)
+                | Command::Begin(_)
+                | Command::End(_)
+                | Command::Mark(_)
+        )
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            exit_code(&err)
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<ExitCode> {
+    // Nothing else can be writing to our stdin or stdout
+    let _lock = if cli.mode.mutates() && !is_stdio(&cli.path) {
+        Some(lock(&cli.path, cli.lock_timeout)?)
+    } else {
+        None
+    };
+
+    match cli.mode {
+        Command::Create(args) => {
+            let mut store = event::EventStore::default();
+            args.render.apply_epoch(&mut store);
+            let renderer = args.render.apply(render::RendererBuilder::default()).build();
+            save(&cli.path, renderer, store)?;
+        }
+        Command::Configure(args) => {
+            let (r, mut events) = open(&cli.path)?;
+            args.apply_epoch(&mut events);
+            let renderer = args.apply(r.into()).build();
+            save(&cli.path, renderer, events)?;
         }
         Command::AddActor(args) => {
-            let (r, mut events) = load(&cli.path).unwrap();
-            events
-                .register_actor(event::Actor {
-                    identity: args.identity,
-                    tooltip: args.tooltip
-                })
-                .unwrap();
-            r.render(cli.path, events).unwrap();
+            let (r, mut events) = open(&cli.path)?;
+            events.register_actor(event::Actor {
+                identity: args.identity,
+                tooltip: args.tooltip,
+            })?;
+            save(&cli.path, r, events)?;
         }
         Command::AddEvent(args) => {
-            let (r, mut events) = load(&cli.path).unwrap();
+            let (r, mut events) = open(&cli.path)?;
 
+            let start = args.start.micros(&events)?;
             let kind = match args.duration {
-                Some(duration) => event::EventKind::Span(args.start, Some(duration)),
+                Some(duration) => event::EventKind::Span(start, Some(duration)),
                 None => {
                     if args.endless {
-                        event::EventKind::Span(args.start, None)
+                        event::EventKind::Span(start, None)
                     } else {
-                        event::EventKind::Instant(args.start)
+                        event::EventKind::Instant(start)
                     }
                 }
             };
@@ -95,14 +745,238 @@ fn main() {
             }
 
             let e = event::Event {
+                id: None,
                 fields,
                 value: "".into(),
                 kind,
                 tooltip: args.tooltip
             };
 
-            events.add_event(&args.actor, e).unwrap();
-            r.render(cli.path, events).unwrap();
+            let id = events.add_event(&args.actor, e)?;
+            save(&cli.path, r, events)?;
+
+            // Report the id so callers can refer to this event in edges,
+            // keeping it out of the chart when that goes to stdout
+            if is_stdio(&cli.path) {
+                eprintln!("{id}");
+            } else {
+                println!("{id}");
+            }
+        }
+        Command::AddEdge(args) => {
+            let (r, mut events) = open(&cli.path)?;
+            events.add_edge(args.from, args.to)?;
+            save(&cli.path, r, events)?;
+        }
+        Command::SetEpoch(args) => {
+            let (r, mut events) = open(&cli.path)?;
+            let epoch = args.time.0.map(|time| {
+                let mut epoch = event::Epoch::new(time);
+                if let Some(offset) = args.timezone {
+                    epoch.offset = offset;
+                }
+                epoch
+            });
+            events.set_epoch(epoch);
+            save(&cli.path, r, events)?;
+        }
+        Command::Upgrade => {
+            // Loading migrates the state, so rendering writes it back at
+            // the current version
+            let (r, events) = open(&cli.path)?;
+            save(&cli.path, r, events)?;
+        }
+        Command::Render(args) => {
+            let (r, events) = open(&cli.path)?;
+            let r = match args.dpi {
+                Some(dpi) => render::RendererBuilder::from(r).dpi(Some(dpi)).build(),
+                None => r,
+            };
+            let format = args
+                .format
+                .unwrap_or_else(|| RenderFormat::from_path(&args.output));
+
+            if is_stdio(&args.output) {
+                format.write(&r, std::io::stdout().lock(), &events)?;
+            } else {
+                std::fs::File::create(&args.output)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|file| format.write(&r, std::io::BufWriter::new(file), &events))
+                    .with_context(|| format!("Failed to render {}", args.output.display()))?;
+            }
+        }
+        Command::Import(args) => {
+            let (r, mut events) = open(&cli.path)?;
+
+            let mut input: Box<dyn std::io::BufRead> = match &args.input {
+                Some(input) if !is_stdio(input) => Box::new(std::io::BufReader::new(
+                    std::fs::File::open(input)
+                        .with_context(|| format!("Failed to open {}", input.display()))?,
+                )),
+                _ if is_stdio(&cli.path) => {
+                    anyhow::bail!("Records can't be read from stdin when the chart is")
+                }
+                _ => Box::new(std::io::stdin().lock()),
+            };
+
+            let format = match args.format {
+                Some(format) => format,
+                None => match args.input.as_ref().and_then(|input| input.extension()) {
+                    Some(ext) if ext == "csv" => ImportFormat::Csv,
+                    _ if chrome::is_trace(input.fill_buf()?) => ImportFormat::ChromeTrace,
+                    _ => ImportFormat::Jsonl,
+                },
+            };
+
+            let records = match format {
+                ImportFormat::Jsonl => import::read_json_lines(input),
+                ImportFormat::Csv => import::read_csv(input),
+                // Trace events don't map to lines of the input
+                ImportFormat::ChromeTrace => chrome::read_trace(input)
+                    .map(|records| records.into_iter().map(Into::into).collect()),
+            };
+            // Only the guessed format can be the wrong one
+            let records = match args.format {
+                Some(_) => records?,
+                None => records.with_context(|| {
+                    let name = clap::ValueEnum::to_possible_value(&format)
+                        .map(|value| value.get_name().to_owned())
+                        .unwrap_or_default();
+                    format!(
+                        "Failed to read the input as {name}, pick the format with \
+                         --format jsonl, csv or chrome-trace"
+                    )
+                })?,
+            };
+
+            import::apply(&mut events, records)?;
+            save(&cli.path, r, events)?;
+        }
+        Command::ImportSystemd(args) => {
+            let (r, mut events) = open(&cli.path)?;
+
+            let dump = match args.dump {
+                Some(dump) => systemd::SystemdDump::parse(
+                    &std::fs::read_to_string(&dump)
+                        .with_context(|| format!("Failed to read {}", dump.display()))?,
+                ),
+                None => systemd::SystemdDump::capture(),
+            }?;
+
+            systemd::import(&dump, &mut events)?;
+            save(&cli.path, r, events)?;
+        }
+        Command::Export(args) => {
+            let (_, events) = open(&cli.path)?;
+
+            let output: Box<dyn std::io::Write> = match &args.output {
+                Some(output) if !is_stdio(output) => Box::new(
+                    std::fs::File::create(output)
+                        .with_context(|| format!("Failed to create {}", output.display()))?,
+                ),
+                _ => Box::new(std::io::stdout().lock()),
+            };
+
+            match args.format {
+                ExportFormat::ChromeTrace => chrome::write_trace(&events, output)?,
+            }
+        }
+        Command::Run(args) => {
+            let mut command = std::process::Command::new(&args.command[0]);
+            command.args(&args.command[1..]);
+
+            // A chart on stdin has to be read before the command can take
+            // it, and the command's output must not end up in the chart
+            // written to stdout
+            let piped = if is_stdio(&cli.path) {
+                command.stdout(std::io::stderr());
+                Some(open(&cli.path)?)
+            } else {
+                None
+            };
+
+            let (status, tree) =
+                process::trace(&mut command, std::time::Duration::from_millis(args.interval))?;
+
+            let _lock = if is_stdio(&cli.path) {
+                None
+            } else {
+                Some(lock(&cli.path, cli.lock_timeout)?)
+            };
+
+            // Add to an existing chart, or start one named after the command
+            let (r, mut events) = if let Some(chart) = piped {
+                chart
+            } else if cli.path.exists() {
+                open(&cli.path)?
+            } else {
+                let heading = args.command.join(" ");
+                (
+                    render::RendererBuilder::default().heading(heading).build(),
+                    event::EventStore::default(),
+                )
+            };
+
+            tree.record(&mut events)?;
+            save(&cli.path, r, events)?;
+
+            // Let scripts see how the command itself went
+            return Ok(ExitCode::from(status.code().unwrap_or(1) as u8));
+        }
+        Command::Begin(args) => {
+            let (r, mut events) = open(&cli.path)?;
+            let now = events.monotonic_time(process::monotonic_now());
+            let kind = event::EventKind::Span(now, None);
+            add_now(&mut events, args.actor, kind, args.label, args.color, args.tooltip)?;
+            save(&cli.path, r, events)?;
+        }
+        Command::End(args) => {
+            let (r, mut events) = open(&cli.path)?;
+            let now = events.monotonic_time(process::monotonic_now());
+            events.close_span(&args.actor, &args.label, now)?;
+            save(&cli.path, r, events)?;
+        }
+        Command::Show(args) => {
+            let (_, events) = open(&cli.path)?;
+
+            let color = match args.color {
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+                ColorChoice::Auto => {
+                    use std::io::IsTerminal;
+                    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+                }
+            };
+            let width = args.width.map_or_else(terminal_width, usize::from);
+
+            text::TextRenderer::new(width)
+                .color(color)
+                .render_to_writer(std::io::stdout().lock(), &events)?;
+        }
+        Command::Blame(args) => {
+            let (_, events) = open(&cli.path)?;
+
+            let sort = match args.sort {
+                BlameSort::Total => blame::SortBy::Total,
+                BlameSort::FirstStart => blame::SortBy::FirstStart,
+                BlameSort::LastEnd => blame::SortBy::LastEnd,
+            };
+            let blames = blame::blame(&events, sort);
+
+            let stdout = std::io::stdout().lock();
+            match args.format {
+                BlameFormat::Plain => blame::write_plain(&blames, sort, events.epoch(), stdout)?,
+                BlameFormat::Json => blame::write_json(&blames, stdout)?,
+                BlameFormat::Csv => blame::write_csv(&blames, stdout)?,
+            }
+        }
+        Command::Mark(args) => {
+            let (r, mut events) = open(&cli.path)?;
+            let kind = event::EventKind::Instant(events.monotonic_time(process::monotonic_now()));
+            add_now(&mut events, args.actor, kind, "".into(), args.color, args.tooltip)?;
+            save(&cli.path, r, events)?;
         }
     }
+
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,41 @@
+use std::path::Path;
+use std::process::Command;
+
+fn chartr(chart: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_chartr"))
+        .arg(chart)
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "chartr {args:?} failed");
+}
+
+/// The width of the root svg element
+fn svg_width(svg: &str) -> f64 {
+    let start = svg.find("width=\"").unwrap() + "width=\"".len();
+    let end = start + svg[start..].find('"').unwrap();
+    svg[start..end].parse().unwrap()
+}
+
+#[test]
+fn test_begin_end() {
+    let chart = Path::new("/tmp/cli-begin.svg");
+    let _ = std::fs::remove_file(chart);
+    chartr(chart, &["create"]);
+
+    // Spans start at the chart's own time zero rather than at boot, so an
+    // open span is drawn from there to the latest time
+    chartr(chart, &["begin", "build", "compile"]);
+    let open = std::fs::read_to_string(chart).unwrap();
+    assert!(!open.contains("width=\"-"));
+    assert!(svg_width(&open) < 100.0);
+
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    chartr(chart, &["end", "build", "compile"]);
+    chartr(chart, &["mark", "build"]);
+    let closed = std::fs::read_to_string(chart).unwrap();
+    assert!(closed.contains("class=\"span\""));
+    assert!(closed.contains("class=\"instant\""));
+    assert!(!closed.contains("width=\"-"));
+    assert!(svg_width(&closed) < 100.0);
+}